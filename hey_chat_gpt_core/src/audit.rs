@@ -0,0 +1,226 @@
+//! APIに送ったもの・受け取ったものの監査ログ
+//!
+//! キャッシュディレクトリの `audit/audit.jsonl` に1リクエスト1行で追記していく。
+//! キャッシュヒット (何も送っていない) も記録する
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cache::get_state_dir;
+use super::config::{relative_to_manifest_dir, Config, Settings};
+
+const AUDIT_DIR_NAME: &str = "audit";
+const AUDIT_FILE_NAME: &str = "audit.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// キャッシュから返した (何も送っていない)
+    Hit,
+    /// APIに送って応答を受け取った
+    Miss,
+    /// APIに送ったが応答を受け取れなかった
+    Failed,
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hit" => Ok(Self::Hit),
+            "miss" => Ok(Self::Miss),
+            "failed" => Ok(Self::Failed),
+            _ => Err(format!(
+                "unknown outcome `{}` (expected \"hit\", \"miss\" or \"failed\")",
+                s
+            )),
+        }
+    }
+}
+
+/// 1回の記録
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// UNIX時刻 (秒)
+    pub timestamp: u64,
+    /// マニフェストディレクトリからの相対パス
+    pub file: String,
+    pub cache_key: String,
+    pub outcome: Outcome,
+    pub model: String,
+    pub endpoint: String,
+    /// 送ったリクエスト本文のSHA-256 (キャッシュヒットでは `None`)
+    pub request_sha256: Option<String>,
+    /// `audit_full_body = true` の場合のみ
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<serde_json::Value>,
+    pub response_id: Option<String>,
+}
+
+/// 記録先と、どのリクエストについての記録か
+#[derive(Debug, Clone)]
+pub struct Audit {
+    path: PathBuf,
+    file: String,
+    cache_key: String,
+    full_body: bool,
+}
+
+impl Audit {
+    pub fn new(source_file_path: &Path, cache_key: &str, config: &Config) -> Self {
+        Self::with_path(
+            audit_path(),
+            source_file_path,
+            cache_key,
+            config.audit_full_body,
+        )
+    }
+
+    pub fn with_path(
+        path: PathBuf,
+        source_file_path: &Path,
+        cache_key: &str,
+        full_body: bool,
+    ) -> Self {
+        Self {
+            path,
+            file: relative_to_manifest_dir(source_file_path)
+                .to_string_lossy()
+                .to_string(),
+            cache_key: cache_key.to_string(),
+            full_body,
+        }
+    }
+
+    pub fn record_hit(&self, settings: &Settings) -> anyhow::Result<()> {
+        self.append(AuditEntry {
+            outcome: Outcome::Hit,
+            model: settings.model().to_string(),
+            endpoint: settings.endpoint().to_string(),
+            request_sha256: None,
+            request_body: None,
+            response_id: None,
+            ..self.entry()
+        })
+    }
+
+    /// `request_body` は実際に送ったJSON
+    pub fn record_request(
+        &self,
+        outcome: Outcome,
+        model: &str,
+        endpoint: &str,
+        request_body: &str,
+        response_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        let request_body_value = match self.full_body {
+            true => Some(serde_json::from_str(request_body)?),
+            false => None,
+        };
+        self.append(AuditEntry {
+            outcome,
+            model: model.to_string(),
+            endpoint: endpoint.to_string(),
+            request_sha256: Some(format!("{:x}", Sha256::digest(request_body))),
+            request_body: request_body_value,
+            response_id,
+            ..self.entry()
+        })
+    }
+
+    fn entry(&self) -> AuditEntry {
+        AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs()),
+            file: self.file.clone(),
+            cache_key: self.cache_key.clone(),
+            outcome: Outcome::Hit,
+            model: String::new(),
+            endpoint: String::new(),
+            request_sha256: None,
+            request_body: None,
+            response_id: None,
+        }
+    }
+
+    fn append(&self, entry: AuditEntry) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        // 並列に呼ばれても行が混ざらないよう、1行を1回で書き込む
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+pub fn audit_path() -> PathBuf {
+    get_state_dir().join(AUDIT_DIR_NAME).join(AUDIT_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_load() {
+        let dir = std::env::temp_dir().join(format!("hey_chat_gpt_audit_{}", std::process::id()));
+        let path = dir.join(AUDIT_FILE_NAME);
+        let _ = fs::remove_file(&path);
+        let source_file_path = Path::new("src/lib.rs");
+        let body = r#"{"model":"gpt-4o","messages":[]}"#;
+
+        let audit = Audit::with_path(path.clone(), source_file_path, "0123abcd", false);
+        audit.record_hit(&Settings::default()).unwrap();
+        audit
+            .record_request(
+                Outcome::Miss,
+                "gpt-4o",
+                "http://localhost/v1/chat/completions",
+                body,
+                Some("chatcmpl-1".to_string()),
+            )
+            .unwrap();
+        let audit = Audit::with_path(path.clone(), source_file_path, "0123abcd", true);
+        audit
+            .record_request(Outcome::Failed, "gpt-4o", "http://localhost", body, None)
+            .unwrap();
+
+        let entries = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.file == "src/lib.rs" && entry.cache_key == "0123abcd"));
+        assert_eq!(entries[0].outcome, Outcome::Hit);
+        assert_eq!(entries[0].request_sha256, None);
+        assert_eq!(entries[1].outcome, Outcome::Miss);
+        assert_eq!(entries[1].response_id.as_deref(), Some("chatcmpl-1"));
+        assert_eq!(
+            entries[1].request_sha256.as_deref(),
+            Some(format!("{:x}", Sha256::digest(body)).as_str())
+        );
+        assert_eq!(entries[1].request_body, None);
+        assert_eq!(
+            entries[2].request_body,
+            Some(serde_json::from_str(body).unwrap())
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
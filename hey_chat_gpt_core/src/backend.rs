@@ -0,0 +1,402 @@
+//! キャッシュの保存先
+//!
+//! ローカルのキャッシュディレクトリに加えて、HTTPサーバーに `GET` / `PUT <url>/<キー>` でチームやCIと共有できる
+//!
+//! サーバーから受け取ったものは信用できないので、共有の秘密鍵があればHMACで署名を確かめる
+
+use anyhow::{bail, Context};
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::query::client_builder;
+use super::util::write_atomic;
+
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(30);
+/// 落ちているサーバーでビルドを待たせないよう、つながるまでは短く待つ
+const REMOTE_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub trait CacheBackend {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
+    fn put(&self, key: &str, response: &str) -> anyhow::Result<()>;
+}
+
+/// `<キャッシュディレクトリ>/objects/<先頭2文字>/<キー>.txt`
+pub struct FsBackend {
+    dir: PathBuf,
+}
+
+impl FsBackend {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn object_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join("objects")
+            .join(key.get(..2).unwrap_or(key))
+            .join(format!("{}.txt", key))
+    }
+}
+
+impl CacheBackend for FsBackend {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let path = self.object_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let response = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Some(response))
+    }
+
+    fn put(&self, key: &str, response: &str) -> anyhow::Result<()> {
+        let path = self.object_path(key);
+        write_atomic(&path, response).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// リモートキャッシュに書き込むか
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    #[default]
+    ReadWrite,
+    /// 読むだけ (CIなど)
+    ReadOnly,
+}
+
+impl std::str::FromStr for CacheMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_write" => Ok(Self::ReadWrite),
+            "read_only" => Ok(Self::ReadOnly),
+            _ => Err(format!(
+                "unknown cache mode `{}` (expected \"read_write\" or \"read_only\")",
+                s
+            )),
+        }
+    }
+}
+
+/// サーバーには問い合わせられたが、エントリが使えなかった (サーバーが落ちているのとは区別する)
+#[derive(Debug)]
+pub struct InvalidEntry(String);
+
+impl std::fmt::Display for InvalidEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidEntry {}
+
+/// サーバーに置くエントリ
+///
+/// `sha256` は同じ本文から計算できるので、取り違えや壊れたものしか検出できない。
+/// 改ざんは秘密鍵を知らないと作れない `hmac` で検出する
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoteEntry {
+    key: String,
+    sha256: String,
+    /// キーと応答に対するHMAC-SHA256。秘密鍵なしで置かれたエントリでは `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hmac: Option<String>,
+    response: String,
+}
+
+/// キーと応答に対するHMAC-SHA256
+fn mac(secret: &str, key: &str, response: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    // 区切りがずれて別の組み合わせと同じにならないよう、長さを前に置く
+    for part in [key, response] {
+        mac.update(&(part.len() as u64).to_le_bytes());
+        mac.update(part.as_bytes());
+    }
+    mac
+}
+
+fn sign(secret: &str, key: &str, response: &str) -> String {
+    format!("{:x}", mac(secret, key, response).finalize().into_bytes())
+}
+
+/// 比べるのにかかる時間から署名を推測されないよう、 `verify_slice` で確かめる
+fn verify(secret: &str, key: &str, response: &str, signature: &str) -> bool {
+    decode_hex(signature)
+        .is_some_and(|signature| mac(secret, key, response).verify_slice(&signature).is_ok())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub struct HttpBackend {
+    url: String,
+    token: Option<String>,
+    /// エントリに署名する共有の秘密鍵。あれば署名のないエントリは使わない
+    secret: Option<String>,
+    mode: CacheMode,
+    client: Client,
+}
+
+impl HttpBackend {
+    pub fn new(
+        url: &str,
+        token: Option<String>,
+        secret: Option<String>,
+        mode: CacheMode,
+    ) -> anyhow::Result<Self> {
+        let client = client_builder()?
+            .connect_timeout(REMOTE_CONNECT_TIMEOUT)
+            .timeout(REMOTE_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            secret,
+            mode,
+            client,
+        })
+    }
+
+    fn entry_url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, key)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+impl CacheBackend for HttpBackend {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let url = self.entry_url(key);
+        let response = self
+            .authorize(self.client.get(&url))
+            .send()
+            .with_context(|| format!("failed to reach the remote cache {}", url))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => {
+                bail!("the remote cache returned {} for {}", status, url)
+            }
+            _ => {}
+        }
+
+        let body = response
+            .text()
+            .with_context(|| format!("failed to download {}", url))?;
+        let entry = serde_json::from_str::<RemoteEntry>(&body)
+            .ok()
+            .filter(|entry| {
+                entry.key == key && format!("{:x}", Sha256::digest(&entry.response)) == entry.sha256
+            });
+        let Some(entry) = entry else {
+            return Err(
+                InvalidEntry(format!("the remote cache entry {} is corrupted", url)).into(),
+            );
+        };
+        if let Some(secret) = &self.secret {
+            let signed = entry
+                .hmac
+                .as_deref()
+                .is_some_and(|signature| verify(secret, &entry.key, &entry.response, signature));
+            if !signed {
+                return Err(InvalidEntry(format!(
+                    "the remote cache entry {} is not signed with HEY_GPT_CACHE_SECRET",
+                    url
+                ))
+                .into());
+            }
+        }
+        Ok(Some(entry.response))
+    }
+
+    fn put(&self, key: &str, response: &str) -> anyhow::Result<()> {
+        if self.mode == CacheMode::ReadOnly {
+            return Ok(());
+        }
+
+        let url = self.entry_url(key);
+        let entry = RemoteEntry {
+            key: key.to_string(),
+            sha256: format!("{:x}", Sha256::digest(response)),
+            hmac: self
+                .secret
+                .as_deref()
+                .map(|secret| sign(secret, key, response)),
+            response: response.to_string(),
+        };
+        let status = self
+            .authorize(self.client.put(&url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&entry)?)
+            .send()
+            .with_context(|| format!("failed to upload to the remote cache {}", url))?
+            .status();
+        if !status.is_success() {
+            bail!("the remote cache returned {} for PUT {}", status, url);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Store = Arc<Mutex<HashMap<String, String>>>;
+
+    /// `GET` と `PUT` だけを受け付けるキャッシュサーバー
+    fn cache_server() -> (String, Store) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache", listener.local_addr().unwrap());
+        let store = Store::default();
+        let server_store = store.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap().to_string());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut store = server_store.lock().unwrap();
+                let (status, body) = match (method, store.get(&path)) {
+                    ("PUT", _) => {
+                        store.insert(path, String::from_utf8(body).unwrap());
+                        ("201 Created", String::new())
+                    }
+                    ("GET", Some(entry)) => ("200 OK", entry.clone()),
+                    _ => ("404 Not Found", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, store)
+    }
+
+    #[test]
+    fn test_http_backend() {
+        let (url, store) = cache_server();
+        let key = "a".repeat(64);
+
+        let backend = HttpBackend::new(&url, None, None, CacheMode::ReadWrite).unwrap();
+        assert_eq!(backend.get(&key).unwrap(), None);
+        backend.put(&key, "fn main() {}").unwrap();
+        assert_eq!(backend.get(&key).unwrap().as_deref(), Some("fn main() {}"));
+
+        // 読むだけの場合は書き込まない
+        let read_only = HttpBackend::new(&url, None, None, CacheMode::ReadOnly).unwrap();
+        read_only.put(&"b".repeat(64), "fn f() {}").unwrap();
+        assert_eq!(read_only.get(&"b".repeat(64)).unwrap(), None);
+        assert_eq!(store.lock().unwrap().len(), 1);
+
+        // 壊れたエントリや、別のキーのエントリは受け付けない
+        let path = format!("/cache/{}", key);
+        let entry = store.lock().unwrap()[&path].clone();
+        store.lock().unwrap().insert(
+            path.clone(),
+            entry.replace("fn main() {}", "fn main() { evil() }"),
+        );
+        assert!(backend.get(&key).is_err());
+        store
+            .lock()
+            .unwrap()
+            .insert(format!("/cache/{}", "c".repeat(64)), entry);
+        assert!(backend.get(&"c".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_http_backend_signed() {
+        let (url, store) = cache_server();
+        let key = "d".repeat(64);
+        let path = format!("/cache/{}", key);
+        let signed =
+            HttpBackend::new(&url, None, Some("secret".into()), CacheMode::ReadWrite).unwrap();
+
+        signed.put(&key, "fn main() {}").unwrap();
+        assert_eq!(signed.get(&key).unwrap().as_deref(), Some("fn main() {}"));
+
+        // ハッシュを計算し直して書き換えても、秘密鍵がなければ署名は作れない
+        let mut entry: RemoteEntry = serde_json::from_str(&store.lock().unwrap()[&path]).unwrap();
+        entry.response = "fn main() { evil() }".to_string();
+        entry.sha256 = format!("{:x}", Sha256::digest(&entry.response));
+        store
+            .lock()
+            .unwrap()
+            .insert(path.clone(), serde_json::to_string(&entry).unwrap());
+        assert!(signed.get(&key).is_err());
+
+        // 署名のないエントリも、別の秘密鍵で署名されたエントリも使わない
+        let unsigned = HttpBackend::new(&url, None, None, CacheMode::ReadWrite).unwrap();
+        unsigned.put(&key, "fn main() {}").unwrap();
+        assert!(signed.get(&key).is_err());
+        let other =
+            HttpBackend::new(&url, None, Some("other".into()), CacheMode::ReadWrite).unwrap();
+        other.put(&key, "fn main() {}").unwrap();
+        assert!(signed.get(&key).is_err());
+        assert!(unsigned.get(&key).is_ok());
+    }
+
+    #[test]
+    fn test_fs_backend() {
+        let dir = std::env::temp_dir().join(format!("hey_gpt_backend_test_{}", std::process::id()));
+        let backend = FsBackend::new(dir.clone());
+        let key = "0123".repeat(16);
+
+        assert_eq!(backend.get(&key).unwrap(), None);
+        backend.put(&key, "fn main() {}").unwrap();
+        assert_eq!(backend.get(&key).unwrap().as_deref(), Some("fn main() {}"));
+        assert_eq!(
+            backend.object_path(&key),
+            dir.join("objects/01").join(format!("{}.txt", key))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
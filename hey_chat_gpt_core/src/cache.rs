@@ -0,0 +1,576 @@
+//! 応答と会話履歴は送る内容と応答を変える設定のSHA-256をキーに `<キャッシュディレクトリ>/objects/<先頭2文字>/<キー>.txt` に保存する
+//!
+//! キーはファイルの場所によらないので、ワークスペースの複数のクレートや他のマシンとキャッシュディレクトリを共有できる。
+//! 書き込みは一時ファイルからの名前の変更で行うので、並行するrustcから読んでも書きかけのファイルは見えない。
+//! リモートキャッシュが設定されていれば、ローカルになかった応答を取りに行き、新しい応答を置きに行く。
+//! `<キー>.meta.json` には整理 ([`super::gc`]) のために生成元と最後に使われた時刻を記録する
+
+use anyhow::Context;
+use proc_macro2::TokenStream;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::backend::{CacheBackend, FsBackend, HttpBackend, InvalidEntry};
+use super::config::{manifest_dir, relative_to_manifest_dir, Config, Settings};
+use super::gc::collect_if_due;
+use super::ide::is_ide_expansion;
+use super::locate::token_strings;
+use super::query::Message;
+use super::util::{lock_file, write_atomic};
+
+/// 以前の形式のキャッシュの場所 (クレートのディレクトリから)
+const LEGACY_CACHE_DIR: &str = "gpt_responses";
+
+/// 応答と会話履歴の保存先 (共有されていることがある)
+pub fn get_cache_dir() -> PathBuf {
+    // 設定の誤りはマクロ展開の最初に報告されるので、ここではデフォルトにフォールバックする
+    Config::load().unwrap_or_default().cache_dir()
+}
+
+/// 生成したコードや台帳などクレートごとのファイルの保存先
+pub fn get_state_dir() -> PathBuf {
+    let state_dir = Config::load().unwrap_or_default().state_dir();
+    // 並行するプロセスが同時に作っても失敗しない
+    fs::create_dir_all(&state_dir).expect("Failed to create cache directory");
+
+    state_dir
+}
+
+/// キャッシュのキー。マシンやRustのバージョンによらず同じになる
+///
+/// 設定ファイルや環境変数でモデルやシステムメッセージを変えたら別のキーになるよう、
+/// 送る内容だけでなく応答を変える設定 ([`Settings::request_fingerprint`]) とシステムメッセージも含める。
+/// `context = "none"` では送る内容に `refine` の指示が入らないので `refine` を、
+/// `context = "crate"` では他のモジュールや依存関係が変わったら生成し直すよう組み立てた `context` を別に含める
+pub fn cache_key(
+    content: &str,
+    settings: &Settings,
+    system_message: &str,
+    refine: Option<&str>,
+    context: Option<&str>,
+) -> String {
+    let mut hasher = Sha256::new();
+    let mut update = |part: &str| {
+        // 区切りがずれて別の組み合わせと同じにならないよう、長さを前に置く
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    };
+    update(&settings.request_fingerprint());
+    update(system_message);
+    update(content);
+    // どちらもないキーは以前と同じになるよう、ある時だけ名前を付けて足す
+    for (name, part) in [("refine", refine), ("context", context)] {
+        if let Some(part) = part {
+            update(name);
+            update(part);
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn get_cache_file_path(key: &str) -> PathBuf {
+    FsBackend::new(get_cache_dir()).object_path(key)
+}
+
+/// このキーのリクエストを送るためのロック。先に送っているプロセスがあれば、その応答が保存されるまで待つ
+pub fn lock_key(key: &str) -> anyhow::Result<File> {
+    let path = get_cache_dir().join("locks").join(format!("{}.lock", key));
+    lock_file(&path).with_context(|| format!("failed to lock {}", path.display()))
+}
+
+/// 一度失敗したリモートキャッシュには、このプロセスの間はもう問い合わせない (呼び出しのたびに待たせない)
+static REMOTE_DISABLED: AtomicBool = AtomicBool::new(false);
+/// リモートキャッシュについての警告
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn remote_failed(err: anyhow::Error, consequence: &str) {
+    // 使えないエントリがあっただけなら、他のエントリは問い合わせる
+    if !err.is::<InvalidEntry>() {
+        REMOTE_DISABLED.store(true, Ordering::Relaxed);
+    }
+    WARNINGS
+        .lock()
+        .unwrap()
+        .push(format!("{:#}; {}", err, consequence));
+}
+
+/// たまった警告を取り出す
+///
+/// マクロ展開中に標準エラーへ出してもビルドの出力に紛れるだけなので、 `cargo hey-gpt` のコマンドだけが表示する
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut WARNINGS.lock().unwrap())
+}
+
+/// ローカルのキャッシュディレクトリ、設定されていればリモートキャッシュの順
+fn backends() -> anyhow::Result<Vec<Box<dyn CacheBackend>>> {
+    let config = Config::load()?;
+    let mut backends: Vec<Box<dyn CacheBackend>> =
+        vec![Box::new(FsBackend::new(config.cache_dir()))];
+    // IDEはキー入力のたびに展開するので、ネットワークには出ない
+    let remote = !is_ide_expansion() && !REMOTE_DISABLED.load(Ordering::Relaxed);
+    if let (Some(url), true) = (config.cache_url(), remote) {
+        let token = std::env::var("HEY_GPT_CACHE_TOKEN").ok();
+        let secret = std::env::var("HEY_GPT_CACHE_SECRET").ok();
+        backends.push(Box::new(HttpBackend::new(
+            &url,
+            token,
+            secret,
+            config.cache_mode()?,
+        )?));
+    }
+    Ok(backends)
+}
+
+pub fn load_cache(key: &str) -> anyhow::Result<Option<String>> {
+    collect_if_due(key)?;
+
+    let cache_file = get_cache_file_path(key);
+    match fs::read_to_string(&cache_file) {
+        Ok(response) => {
+            touch(&cache_file);
+            return Ok(Some(response));
+        }
+        // まだないか、他のプロセスの整理で消えた
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", cache_file.display()))
+        }
+    }
+
+    let backends = backends()?;
+    for (i, backend) in backends.iter().enumerate().skip(1) {
+        // サーバーが落ちていたり、使えないエントリだったりしてもビルドは止めず、なかったことにする
+        let response = match backend.get(key) {
+            Ok(response) => response,
+            Err(err) => {
+                remote_failed(err, "treating it as a cache miss");
+                None
+            }
+        };
+        if let Some(response) = response {
+            // 次からはローカルで見つかるよう、手前の保存先にも置く
+            for backend in &backends[..i] {
+                backend.put(key, &response)?;
+            }
+            touch(&cache_file);
+            return Ok(Some(response));
+        }
+    }
+    Ok(None)
+}
+
+/// すべての保存先に結果を保存する (読むだけのリモートキャッシュは除く)
+///
+/// `caller` は同じファイルの他の呼び出しと区別する値 ([`caller_id`])
+pub fn cache_result(
+    key: &str,
+    response: &str,
+    source_file_path: &Path,
+    caller: &str,
+    model: &str,
+) -> anyhow::Result<()> {
+    let backends = backends()?;
+    let (local, remotes) = backends
+        .split_first()
+        .expect("the local cache always exists");
+    local.put(key, response)?;
+    // リモートに置けなくてもローカルには保存できているので、ビルドは止めない
+    for backend in remotes {
+        if let Err(err) = backend.put(key, response) {
+            remote_failed(err, "skipped the upload");
+        }
+    }
+
+    let now = unix_now();
+    let meta = EntryMeta {
+        source: Some(source_id(source_file_path)),
+        caller: Some(caller.to_string()),
+        model: Some(model.to_string()),
+        created: now,
+        accessed: now,
+    };
+    let meta_path = meta_path(&get_cache_file_path(key));
+    write_atomic(&meta_path, &serde_json::to_string(&meta)?)
+        .with_context(|| format!("failed to write {}", meta_path.display()))
+}
+
+/// キャッシュのエントリごとの情報
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntryMeta {
+    /// 生成元のファイル (クレートのディレクトリを含む)。リモートから取ってきたものなどでは `None`
+    pub source: Option<String>,
+    /// 生成元の呼び出し ([`caller_id`])。以前のキャッシュやリモートから取ってきたものでは `None`
+    #[serde(default)]
+    pub caller: Option<String>,
+    /// 生成したモデル。以前のキャッシュやリモートから取ってきたものでは `None`
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 保存した時刻 (UNIX時間)
+    pub created: u64,
+    /// 最後に使われた時刻 (UNIX時間)
+    pub accessed: u64,
+}
+
+/// `<キー>.txt` に対する `<キー>.meta.json`
+pub fn meta_path(cache_file: &Path) -> PathBuf {
+    cache_file.with_extension("meta.json")
+}
+
+/// 情報がなければ (以前のキャッシュなど) ファイルの更新時刻で代用する
+pub fn load_entry_meta(cache_file: &Path) -> EntryMeta {
+    if let Some(meta) = fs::read_to_string(meta_path(cache_file))
+        .ok()
+        .and_then(|meta| serde_json::from_str(&meta).ok())
+    {
+        return meta;
+    }
+    let modified = fs::metadata(cache_file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_secs());
+    EntryMeta {
+        created: modified,
+        accessed: modified,
+        ..EntryMeta::default()
+    }
+}
+
+/// 最後に使われた時刻を更新する。ビルドのたびに書き込まないよう、1分以内なら更新しない
+fn touch(cache_file: &Path) {
+    let mut meta = load_entry_meta(cache_file);
+    let now = unix_now();
+    if now.saturating_sub(meta.accessed) < 60 {
+        return;
+    }
+    meta.accessed = now;
+    if let Ok(meta) = serde_json::to_string(&meta) {
+        // 記録できなくても整理の順番が変わるだけなので無視する
+        let _ = write_atomic(&meta_path(cache_file), &meta);
+    }
+}
+
+/// キャッシュディレクトリを共有するクレートの間で区別できるよう、クレートのディレクトリを含める
+fn source_id(source_file_path: &Path) -> String {
+    let manifest_dir = manifest_dir();
+    let manifest_dir = fs::canonicalize(&manifest_dir).unwrap_or(manifest_dir);
+    manifest_dir
+        .join(relative_to_manifest_dir(source_file_path))
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+pub fn transcript_file_path(key: &str) -> PathBuf {
+    get_cache_file_path(key).with_extension("transcript.json")
+}
+
+/// 同じファイルの他の呼び出しと区別する値。 `pin.rs` と同じくマクロ名と入力から作る
+///
+/// `refine` を足しても前回の生成の続きが見つかるよう、 `refine = "..."` の指定は除く
+pub fn caller_id(macro_name: &str, tokens: &TokenStream) -> String {
+    let mut strings = token_strings(tokens.clone());
+    while let Some(i) = strings
+        .windows(2)
+        .position(|pair| pair[0] == "refine" && pair[1] == "=")
+    {
+        let end = (i + 3).min(strings.len());
+        let is_separator = |token: Option<&String>| token.is_some_and(|t| t == "," || t == ";");
+        // 区切りも1つ取り除き、最後の指定だった場合は前の区切りを取り除く
+        let range = match (is_separator(strings.get(end)), i.checked_sub(1)) {
+            (true, _) => i..end + 1,
+            (false, Some(prev)) if is_separator(strings.get(prev)) => prev..end,
+            _ => i..end,
+        };
+        strings.drain(range);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(macro_name);
+    hasher.update(" ");
+    hasher.update(strings.join(" "));
+    format!("{:x}", hasher.finalize())
+}
+
+fn latest_file_path(source_file_path: &Path, caller: &str) -> PathBuf {
+    // rustcとrust-analyzerでパスの形が違っても同じファイルになるよう、マニフェストディレクトリからの相対パスにする
+    let source_file_path = relative_to_manifest_dir(source_file_path);
+    get_state_dir().join(format!(
+        "latest_{}.txt",
+        hash_content(&format!(
+            "{}\n{}",
+            source_file_path.to_string_lossy(),
+            caller
+        ))
+    ))
+}
+
+/// 会話履歴を保存し、この呼び出しの最新の生成として記録する
+pub fn cache_transcript(
+    key: &str,
+    source_file_path: &Path,
+    caller: &str,
+    messages: &[Message],
+) -> anyhow::Result<()> {
+    let transcript_file = transcript_file_path(key);
+    let transcript = serde_json::to_string_pretty(messages)?;
+
+    write_atomic(&transcript_file, &transcript)
+        .with_context(|| format!("failed to write {}", transcript_file.display()))?;
+    mark_latest(key, source_file_path, caller)
+}
+
+/// キャッシュヒット時などに、この呼び出しの最新の生成を付け替える
+pub fn mark_latest(key: &str, source_file_path: &Path, caller: &str) -> anyhow::Result<()> {
+    let latest_file = latest_file_path(source_file_path, caller);
+
+    write_atomic(&latest_file, key)
+        .with_context(|| format!("failed to write {}", latest_file.display()))
+}
+
+/// この呼び出しで最後に使われた生成の応答のファイル
+fn latest_cache_file_path(source_file_path: &Path, caller: &str) -> Option<PathBuf> {
+    let latest_file = latest_file_path(source_file_path, caller);
+    let key = fs::read_to_string(latest_file).ok()?;
+    let cache_file = get_cache_file_path(key.trim());
+
+    cache_file.exists().then_some(cache_file)
+}
+
+/// この呼び出しで最後に使われた生成の会話履歴を読み込む
+pub fn load_latest_transcript(source_file_path: &Path, caller: &str) -> Option<Vec<Message>> {
+    let transcript_file =
+        latest_cache_file_path(source_file_path, caller)?.with_extension("transcript.json");
+    let transcript = fs::read_to_string(transcript_file).ok()?;
+
+    serde_json::from_str(&transcript).ok()
+}
+
+/// この呼び出しで最後に使われた生成の応答を読み込む
+pub fn load_latest_response(source_file_path: &Path, caller: &str) -> Option<String> {
+    fs::read_to_string(latest_cache_file_path(source_file_path, caller)?).ok()
+}
+
+/// 以前の形式 (`<クレート>/gpt_responses/cache_<ファイル全体のハッシュ>.txt`) のキャッシュを読み込む
+///
+/// 以前はファイルの内容だけをキーにしていたので、同じ内容なら設定によらずその応答を使っていた
+pub fn load_legacy_cache(file_content: &str) -> Option<String> {
+    let legacy_file = manifest_dir()
+        .join(LEGACY_CACHE_DIR)
+        .join(format!("cache_{}.txt", hash_content(file_content)));
+    fs::read_to_string(legacy_file).ok()
+}
+
+/// シード値などに使う
+pub fn hash_content(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TEST_ENV_LOCK;
+    use crate::query::Role;
+    use quote::quote;
+    use std::sync::{MutexGuard, PoisonError};
+
+    /// テストの間だけキャッシュディレクトリを一時ディレクトリにして、終わったら消す
+    struct TempCacheDir {
+        dir: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl TempCacheDir {
+        fn new(name: &str) -> Self {
+            let lock = TEST_ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+            let dir = std::env::temp_dir().join(format!(
+                "hey_gpt_cache_test_{}_{}",
+                name,
+                std::process::id()
+            ));
+            std::env::set_var("HEY_GPT_CACHE_DIR", &dir);
+            Self { dir, _lock: lock }
+        }
+    }
+
+    impl Drop for TempCacheDir {
+        fn drop(&mut self) {
+            std::env::remove_var("HEY_GPT_CACHE_DIR");
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn test_cache_layout() {
+        let cache_dir = TempCacheDir::new("layout");
+        let settings = Settings::default();
+        let key = cache_key("fn main() {}", &settings, "system", None, None);
+        assert_eq!(key.len(), 64);
+        assert_eq!(
+            key,
+            cache_key("fn main() {}", &settings, "system", None, None)
+        );
+        assert_ne!(
+            key,
+            cache_key("fn main() { }", &settings, "system", None, None)
+        );
+        // 応答を変える設定やシステムメッセージが違えば別のキーになる
+        let other_model = Settings {
+            model: Some("o3".to_string()),
+            ..Settings::default()
+        };
+        assert_ne!(
+            key,
+            cache_key("fn main() {}", &other_model, "system", None, None)
+        );
+        assert_ne!(
+            key,
+            cache_key("fn main() {}", &settings, "システム", None, None)
+        );
+        // 送る内容が変わらない設定は含めない
+        let other_retries = Settings {
+            max_retries: Some(10),
+            ..Settings::default()
+        };
+        assert_eq!(
+            key,
+            cache_key("fn main() {}", &other_retries, "system", None, None)
+        );
+        // refineの指示やクレートのコンテキストが変われば別のキーになる
+        let refined = cache_key("fn main() {}", &settings, "system", Some("use u64"), None);
+        assert_ne!(key, refined);
+        assert_ne!(
+            refined,
+            cache_key("fn main() {}", &settings, "system", Some("add docs"), None)
+        );
+        let with_context = cache_key(
+            "fn main() {}",
+            &settings,
+            "system",
+            None,
+            Some("pub fn f()"),
+        );
+        assert_ne!(key, with_context);
+        assert_ne!(
+            with_context,
+            cache_key(
+                "fn main() {}",
+                &settings,
+                "system",
+                None,
+                Some("pub fn g()")
+            )
+        );
+        // 名前を付けて足すので、refineとコンテキストを取り違えても同じにならない
+        assert_ne!(
+            with_context,
+            cache_key(
+                "fn main() {}",
+                &settings,
+                "system",
+                Some("pub fn f()"),
+                None
+            )
+        );
+
+        let path = get_cache_file_path(&key);
+        assert_eq!(
+            path,
+            cache_dir
+                .dir
+                .join("objects")
+                .join(&key[..2])
+                .join(format!("{}.txt", key))
+        );
+        assert_eq!(
+            transcript_file_path(&key).file_name().unwrap(),
+            format!("{}.transcript.json", key).as_str()
+        );
+    }
+
+    #[test]
+    fn test_caller_id() {
+        let id = caller_id("do_it", &quote! { "fib", context = "none" });
+        assert_eq!(
+            id,
+            caller_id("do_it", &"\"fib\" ,\n context = \"none\"".parse().unwrap())
+        );
+        assert_ne!(id, caller_id("do_it", &quote! { "fact", context = "none" }));
+        assert_ne!(
+            id,
+            caller_id("あとは任せた", &quote! { "fib", context = "none" })
+        );
+        // refineを足しても同じ呼び出しとみなす
+        assert_eq!(
+            id,
+            caller_id(
+                "do_it",
+                &quote! { "fib", refine = "use u64", context = "none" }
+            )
+        );
+        assert_eq!(
+            id,
+            caller_id(
+                "do_it",
+                &quote! { "fib", context = "none", refine = "use u64" }
+            )
+        );
+    }
+
+    #[test]
+    fn test_latest_transcript() {
+        let _cache_dir = TempCacheDir::new("transcript");
+        let source = PathBuf::from(format!("src/transcript_test_{}.rs", std::process::id()));
+        let content = format!("// {}\nfn main() {{}}", source.display());
+        let caller = caller_id("do_it", &quote! { "fib" });
+        let other = caller_id("do_it", &quote! { "fact" });
+        let messages = vec![
+            Message {
+                role: Role::User,
+                content: content.clone(),
+            },
+            Message {
+                role: Role::Assistant,
+                content: "fn f() {}".to_string(),
+            },
+        ];
+
+        // まだ生成していないファイルには続きがない
+        assert_eq!(load_latest_transcript(&source, &caller), None);
+        assert_eq!(load_latest_response(&source, &caller), None);
+
+        let key = cache_key(&content, &Settings::default(), "system", None, None);
+        let cache_file = get_cache_file_path(&key);
+        write_atomic(&cache_file, "fn f() {}").unwrap();
+        cache_transcript(&key, &source, &caller, &messages).unwrap();
+        assert_eq!(load_latest_transcript(&source, &caller), Some(messages));
+        assert_eq!(
+            load_latest_response(&source, &caller).as_deref(),
+            Some("fn f() {}")
+        );
+        // 同じファイルの他の呼び出しの続きにはしない
+        assert_eq!(load_latest_transcript(&source, &other), None);
+
+        // 応答が整理で消えていれば、会話履歴が残っていても続きにしない
+        fs::remove_file(&cache_file).unwrap();
+        assert_eq!(load_latest_transcript(&source, &caller), None);
+
+        fs::remove_file(latest_file_path(&source, &caller)).unwrap();
+        // キャッシュディレクトリがクレートの外なので、 `latest_*.txt` はクレートの `gpt_responses` に書かれる。空なら消す
+        let _ = fs::remove_dir(get_state_dir());
+    }
+}
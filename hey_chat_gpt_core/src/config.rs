@@ -0,0 +1,641 @@
+//! 設定は 組み込みのデフォルト < 設定ファイル(< パスごとのルール) < 環境変数 < マクロ引数 の順に上書きされる
+//!
+//! 設定ファイルは `$CARGO_MANIFEST_DIR/hey_gpt.toml` か、
+//! なければ `Cargo.toml` の `[package.metadata.hey_chat_gpt]` から読む
+
+use anyhow::{bail, Context};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use super::backend::CacheMode;
+use super::ledger::Price;
+use super::redact::RedactionPolicy;
+use super::util::{ExtractOptions, UntaggedPolicy};
+
+pub const CONFIG_FILE_NAME: &str = "hey_gpt.toml";
+
+const DEFAULT_PROVIDER: &str = "openai";
+const DEFAULT_MODEL: &str = "gpt-4o";
+const DEFAULT_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_CONTEXT_MAX_TOKENS: u64 = 4000;
+const DEFAULT_CACHE_DIR: &str = "gpt_responses";
+
+/// 環境変数を書き換えるテストと、それを読むテストを同時に走らせない
+#[cfg(test)]
+pub(crate) static TEST_ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// 設定の1層分
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub endpoint: Option<String>,
+    pub seed: Option<u64>,
+    pub max_completion_tokens: Option<u64>,
+    pub max_retries: Option<u32>,
+    pub timeout_secs: Option<u64>,
+    /// `Name: value` を `;` で区切ったもの。層を重ねると連結され、同名のものは後勝ち
+    pub headers: Option<String>,
+    pub code_tags: Option<Vec<String>>,
+    pub untagged_fences: Option<UntaggedPolicy>,
+    pub offline: Option<bool>,
+    /// システムメッセージの言語 (`en`, `ja`, `zh`, `ko`, `de` と設定ファイルで追加したもの)
+    pub lang: Option<String>,
+    pub context: Option<ContextMode>,
+    /// `context = "crate"` で送るコンテキストのトークン数の上限
+    pub context_max_tokens: Option<u64>,
+    /// 送信するプロンプトのトークン数の上限 (モデルのコンテキストウィンドウとの小さい方が使われる)
+    pub max_prompt_tokens: Option<u64>,
+    pub redaction: Option<RedactionPolicy>,
+    /// 組み込みの検出器に加えて伏せる値の正規表現
+    pub redact_patterns: Option<Vec<String>>,
+    /// 独自のシステムメッセージ (`{crate_name}` 等のプレースホルダを展開する)
+    pub system_prompt: Option<String>,
+    /// `system_prompt` をファイルから読む (マニフェストディレクトリからの相対パス)
+    pub system_prompt_file: Option<PathBuf>,
+    pub system_prompt_mode: Option<PromptMode>,
+    /// `hey_gpt.lock` に記録した出力を使い続ける
+    pub pin: Option<bool>,
+}
+
+/// ファイル以外に何を送るか
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextMode {
+    /// マクロを含むファイルのみ
+    #[default]
+    File,
+    /// クレートの依存関係と他のモジュールの公開アイテムも送る
+    Crate,
+    /// ファイルは読まず、マクロの入力だけを送る
+    #[serde(alias = "standalone")]
+    None,
+}
+
+impl std::str::FromStr for ContextMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(Self::File),
+            "crate" => Ok(Self::Crate),
+            "none" | "standalone" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown context `{}` (expected \"file\", \"crate\" or \"none\")",
+                s
+            )),
+        }
+    }
+}
+
+/// 独自のシステムメッセージの使い方
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptMode {
+    /// 組み込みのメッセージを置き換える
+    #[default]
+    Replace,
+    /// 組み込みのメッセージに追記する
+    Extend,
+}
+
+impl std::str::FromStr for PromptMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(Self::Replace),
+            "extend" => Ok(Self::Extend),
+            _ => Err(format!(
+                "unknown system_prompt_mode `{}` (expected \"replace\" or \"extend\")",
+                s
+            )),
+        }
+    }
+}
+
+impl Settings {
+    /// `other` で設定されている項目を上書きする
+    pub fn merge(&mut self, other: &Settings) {
+        macro_rules! overwrite {
+            ($($field:ident),*) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                    }
+                )*
+            };
+        }
+        overwrite!(
+            provider,
+            model,
+            endpoint,
+            seed,
+            max_completion_tokens,
+            max_retries,
+            timeout_secs,
+            code_tags,
+            untagged_fences,
+            offline,
+            lang,
+            context,
+            context_max_tokens,
+            max_prompt_tokens,
+            redaction,
+            redact_patterns,
+            system_prompt_mode,
+            pin
+        );
+
+        // 文字列とファイルは同じものの別の指定方法なので、どちらかが指定されたら両方置き換える
+        if other.system_prompt.is_some() || other.system_prompt_file.is_some() {
+            self.system_prompt = other.system_prompt.clone();
+            self.system_prompt_file = other.system_prompt_file.clone();
+        }
+
+        self.headers = match (self.headers.take(), &other.headers) {
+            (Some(headers), Some(other)) => Some(format!("{}; {}", headers, other)),
+            (headers, other) => headers.or(other.clone()),
+        };
+    }
+
+    /// 環境変数による層
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn var(name: &str) -> Option<String> {
+            env::var(name).ok().filter(|value| !value.is_empty())
+        }
+
+        fn parse_var<T: std::str::FromStr>(
+            name: &str,
+            expected: &str,
+        ) -> anyhow::Result<Option<T>> {
+            var(name)
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("{} must be {}", name, expected))
+        }
+
+        Ok(Self {
+            provider: var("HEY_GPT_PROVIDER"),
+            model: var("HEY_GPT_MODEL"),
+            endpoint: var("HEY_GPT_ENDPOINT"),
+            max_retries: parse_var("HEY_GPT_MAX_RETRIES", "a non-negative integer")?,
+            timeout_secs: parse_var("HEY_GPT_TIMEOUT", "a number of seconds")?,
+            headers: var("HEY_GPT_EXTRA_HEADERS"),
+            offline: var("HEY_GPT_OFFLINE").map(|offline| offline != "0"),
+            lang: var("HEY_GPT_LANG"),
+            ..Default::default()
+        })
+    }
+
+    pub fn provider(&self) -> &str {
+        self.provider.as_deref().unwrap_or(DEFAULT_PROVIDER)
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    pub fn endpoint(&self) -> &str {
+        self.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT)
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS))
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline.unwrap_or(false)
+    }
+
+    pub fn pin(&self) -> bool {
+        self.pin.unwrap_or(false)
+    }
+
+    pub fn context(&self) -> ContextMode {
+        self.context.unwrap_or_default()
+    }
+
+    pub fn context_max_tokens(&self) -> u64 {
+        self.context_max_tokens
+            .unwrap_or(DEFAULT_CONTEXT_MAX_TOKENS)
+    }
+
+    pub fn system_prompt_mode(&self) -> PromptMode {
+        self.system_prompt_mode.unwrap_or_default()
+    }
+
+    /// 独自のシステムメッセージ (未展開)
+    pub fn custom_system_prompt(&self) -> anyhow::Result<Option<String>> {
+        if let Some(system_prompt) = &self.system_prompt {
+            return Ok(Some(system_prompt.clone()));
+        }
+        match &self.system_prompt_file {
+            Some(file) => {
+                let path = manifest_dir().join(file);
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Ok(Some(text))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 応答を変える設定を、省略時の値を埋めて並べたもの (キャッシュのキーに含める)
+    ///
+    /// リトライやコードの取り出し方など、送るリクエストが変わらない設定は含めない。
+    /// 言語や独自のシステムメッセージはシステムメッセージとしてキーに含まれる
+    pub fn request_fingerprint(&self) -> String {
+        let settings = Settings {
+            provider: Some(self.provider().to_string()),
+            model: Some(self.model().to_string()),
+            endpoint: Some(self.endpoint().to_string()),
+            seed: self.seed,
+            max_completion_tokens: self.max_completion_tokens,
+            context: Some(self.context()),
+            context_max_tokens: (self.context() == ContextMode::Crate)
+                .then(|| self.context_max_tokens()),
+            max_prompt_tokens: self.max_prompt_tokens,
+            redaction: Some(self.redaction.unwrap_or_default()),
+            redact_patterns: self.redact_patterns.clone(),
+            ..Settings::default()
+        };
+        serde_json::to_string(&settings).expect("Failed to serialize settings")
+    }
+
+    pub fn extract_options(&self) -> ExtractOptions {
+        let mut extract_options = ExtractOptions::default();
+        if let Some(code_tags) = &self.code_tags {
+            extract_options.tags = code_tags.clone();
+        }
+        if let Some(untagged_fences) = self.untagged_fences {
+            extract_options.untagged = untagged_fences;
+        }
+        extract_options
+    }
+}
+
+/// 設定ファイルの `[locales.<lang>]`
+///
+/// 組み込みの言語では指定した項目だけを置き換える。
+/// 新しい言語では `comment_instruction` が必須で、残りは英語のものを使う
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocaleConfig {
+    /// `{comment_instruction}` の位置にコメントの言語の指示が入る
+    pub message: Option<String>,
+    pub comment_instruction: Option<String>,
+    /// ユーザー入力の直前に置く文
+    pub header: Option<String>,
+}
+
+/// パスごとのルール
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// マニフェストディレクトリからの相対パスに対するglob (例: `src/generated/**`)
+    pub path: String,
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// キャッシュの保存先 (マニフェストディレクトリからの相対パス、絶対パスか `~/` で始まるパス)
+    pub cache_dir: Option<PathBuf>,
+    /// チームで共有するリモートキャッシュのURL (`GET` / `PUT <url>/<キー>`)
+    pub cache_url: Option<String>,
+    /// リモートキャッシュに書き込むか
+    pub cache_mode: Option<CacheMode>,
+    /// 最後に使われてからこれ以上経ったキャッシュを消す (`"30d"`、 `"12h"` など)
+    pub max_age: Option<String>,
+    /// キャッシュのエントリ数の上限
+    pub max_entries: Option<usize>,
+    /// キャッシュの合計サイズの上限 (バイト)
+    pub max_bytes: Option<u64>,
+    /// 呼び出しごとに最新のキャッシュだけを残す
+    pub keep_latest_only: bool,
+    /// tiktoken形式のBPEテーブル (マニフェストディレクトリからの相対パス)
+    pub tokenizer_file: Option<PathBuf>,
+    /// クレート全体でのAPI利用料の上限 (USD)
+    pub budget_usd: Option<f64>,
+    /// モデル名の前方一致ごとの料金 (組み込みの料金表より優先)
+    pub pricing: BTreeMap<String, Price>,
+    /// 監査ログにリクエストのハッシュだけでなく本文も記録する
+    pub audit_full_body: bool,
+    #[serde(flatten)]
+    pub settings: Settings,
+    pub rules: Vec<Rule>,
+    pub locales: BTreeMap<String, LocaleConfig>,
+    /// 読み込んだ設定ファイル
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let manifest_dir = manifest_dir();
+
+        let config_file = manifest_dir.join(CONFIG_FILE_NAME);
+        if config_file.exists() {
+            let text = fs::read_to_string(&config_file)
+                .with_context(|| format!("failed to read {}", config_file.display()))?;
+            let mut config: Config = toml::from_str(&text)
+                .with_context(|| format!("invalid {}", config_file.display()))?;
+            config.source = Some(config_file);
+            return Ok(config);
+        }
+
+        let manifest = manifest_dir.join("Cargo.toml");
+        let Ok(text) = fs::read_to_string(&manifest) else {
+            return Ok(Config::default());
+        };
+        let manifest: toml::Table =
+            toml::from_str(&text).with_context(|| format!("invalid {}", manifest.display()))?;
+        let metadata = manifest
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("hey_chat_gpt"));
+        match metadata {
+            Some(metadata) => {
+                let mut config = metadata.clone().try_into::<Config>().with_context(|| {
+                    format!(
+                        "invalid [package.metadata.hey_chat_gpt] in {}",
+                        manifest_dir.join("Cargo.toml").display()
+                    )
+                })?;
+                config.source = Some(manifest_dir.join("Cargo.toml"));
+                Ok(config)
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// 応答と会話履歴の保存先。 `HEY_GPT_CACHE_DIR` が設定ファイルの `cache_dir` より優先される
+    pub fn cache_dir(&self) -> PathBuf {
+        let manifest_dir = manifest_dir();
+        let cache_dir = env::var_os("HEY_GPT_CACHE_DIR")
+            .filter(|cache_dir| !cache_dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| self.cache_dir.clone());
+        match cache_dir {
+            Some(cache_dir) => manifest_dir.join(expand_home(&cache_dir)),
+            None => manifest_dir.join(DEFAULT_CACHE_DIR),
+        }
+    }
+
+    /// リモートキャッシュのURL。 `HEY_GPT_CACHE_URL` が設定ファイルの `cache_url` より優先される
+    pub fn cache_url(&self) -> Option<String> {
+        env::var("HEY_GPT_CACHE_URL")
+            .ok()
+            .or_else(|| self.cache_url.clone())
+            .filter(|url| !url.is_empty())
+    }
+
+    pub fn cache_mode(&self) -> anyhow::Result<CacheMode> {
+        match env::var("HEY_GPT_CACHE_MODE") {
+            Ok(mode) if !mode.is_empty() => mode
+                .parse()
+                .map_err(|err: String| anyhow::anyhow!("HEY_GPT_CACHE_MODE: {}", err)),
+            _ => Ok(self.cache_mode.unwrap_or_default()),
+        }
+    }
+
+    /// クレートごとのファイル (生成したコード、台帳、監査ログ) の保存先
+    ///
+    /// キャッシュがクレートの中にあればそこに、クレートの外で共有されていれば `gpt_responses` に置く
+    pub fn state_dir(&self) -> PathBuf {
+        let manifest_dir = manifest_dir();
+        let cache_dir = self.cache_dir();
+        let inside = cache_dir.strip_prefix(&manifest_dir).is_ok_and(|rest| {
+            rest.components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        });
+        match inside {
+            true => cache_dir,
+            false => manifest_dir.join(DEFAULT_CACHE_DIR),
+        }
+    }
+
+    /// `source_file_path` に対する設定を、マクロ引数を除いて解決する
+    pub fn settings_for(&self, source_file_path: &Path) -> anyhow::Result<Settings> {
+        let mut settings = self.file_settings_for(&relative_to_manifest_dir(source_file_path))?;
+        settings.merge(&Settings::from_env()?);
+
+        Ok(settings)
+    }
+
+    /// 設定ファイルのみによる設定 (`relative` はマニフェストディレクトリからの相対パス)
+    fn file_settings_for(&self, relative: &Path) -> anyhow::Result<Settings> {
+        let mut settings = self.settings.clone();
+
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        for rule in &self.rules {
+            let pattern = Pattern::new(&rule.path)
+                .with_context(|| format!("invalid rule path `{}`", rule.path))?;
+            if pattern.matches_path_with(relative, options) {
+                settings.merge(&rule.settings);
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// 全ての層を重ねた設定を得る
+pub fn resolve(source_file_path: &Path, macro_settings: &Settings) -> anyhow::Result<Settings> {
+    let mut settings = Config::load()?.settings_for(source_file_path)?;
+    settings.merge(macro_settings);
+
+    if settings.provider() != DEFAULT_PROVIDER {
+        bail!(
+            "unknown provider `{}` (only \"{}\" is supported)",
+            settings.provider(),
+            DEFAULT_PROVIDER
+        );
+    }
+
+    Ok(settings)
+}
+
+/// プロンプトのプレースホルダに使うクレートの情報
+#[derive(Debug, Default, Clone)]
+pub struct CrateInfo {
+    pub name: String,
+    pub edition: String,
+    /// `Cargo.toml` の `default` フィーチャー
+    /// (proc-macroからは実際に有効なフィーチャーを知る方法がないため)
+    pub features: Vec<String>,
+    /// `[dependencies]` テーブル (TOML、なければ空文字列)
+    pub dependencies: String,
+}
+
+impl CrateInfo {
+    pub fn load() -> Self {
+        let manifest = fs::read_to_string(manifest_dir().join("Cargo.toml"))
+            .ok()
+            .and_then(|text| toml::from_str::<toml::Table>(&text).ok())
+            .unwrap_or_default();
+        let package = manifest.get("package");
+        let string = |key: &str| {
+            package
+                .and_then(|package| package.get(key))
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+
+        Self {
+            name: string("name")
+                .or_else(|| env::var("CARGO_PKG_NAME").ok())
+                .unwrap_or_default(),
+            // editionの指定がない場合は2015になる
+            edition: string("edition").unwrap_or_else(|| "2015".to_string()),
+            features: manifest
+                .get("features")
+                .and_then(|features| features.get("default"))
+                .and_then(|default| default.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|feature| feature.as_str())
+                .map(str::to_string)
+                .collect(),
+            dependencies: manifest
+                .get("dependencies")
+                .map(|dependencies| {
+                    let mut table = toml::Table::new();
+                    table.insert("dependencies".to_string(), dependencies.clone());
+                    toml::to_string(&table).unwrap_or_default()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub fn manifest_dir() -> PathBuf {
+    PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set"))
+}
+
+/// `~/` で始まるパスをホームディレクトリからのパスにする
+fn expand_home(path: &Path) -> PathBuf {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// rustcから渡されるパスはカレントディレクトリ(ワークスペースのルート)からの相対パスなので、
+/// マニフェストディレクトリからの相対パスに直す
+pub fn relative_to_manifest_dir(path: &Path) -> PathBuf {
+    let absolute = match env::current_dir() {
+        Ok(current_dir) if path.is_relative() => current_dir.join(path),
+        _ => path.to_path_buf(),
+    };
+    let absolute = fs::canonicalize(&absolute).unwrap_or(absolute);
+    let manifest_dir = manifest_dir();
+    let manifest_dir = fs::canonicalize(&manifest_dir).unwrap_or(manifest_dir);
+
+    match absolute.strip_prefix(&manifest_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_dir() {
+        let _lock = TEST_ENV_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let manifest_dir = manifest_dir();
+        let config_with = |cache_dir: &str| Config {
+            cache_dir: Some(PathBuf::from(cache_dir)),
+            ..Config::default()
+        };
+        if env::var_os("HEY_GPT_CACHE_DIR").is_some() {
+            return;
+        }
+
+        let config = config_with("cache");
+        assert_eq!(config.cache_dir(), manifest_dir.join("cache"));
+        assert_eq!(config.state_dir(), manifest_dir.join("cache"));
+
+        let config = config_with("../shared");
+        assert_eq!(config.cache_dir(), manifest_dir.join("../shared"));
+        assert_eq!(config.state_dir(), manifest_dir.join("gpt_responses"));
+
+        let config = config_with("/var/cache/hey_chat_gpt");
+        assert_eq!(config.cache_dir(), Path::new("/var/cache/hey_chat_gpt"));
+        assert_eq!(config.state_dir(), manifest_dir.join("gpt_responses"));
+
+        if let Some(home) = env::var_os("HOME") {
+            let config = config_with("~/.cache/hey_chat_gpt");
+            assert_eq!(
+                config.cache_dir(),
+                PathBuf::from(home).join(".cache/hey_chat_gpt")
+            );
+        }
+    }
+
+    #[test]
+    fn test_layers_and_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            model = "gpt-4o-mini"
+            max_retries = 1
+            headers = "OpenAI-Organization: org-1"
+
+            [[rules]]
+            path = "src/generated/**"
+            model = "o1-preview"
+            headers = "OpenAI-Project: proj-1"
+
+            [[rules]]
+            path = "src/*.rs"
+            timeout_secs = 10
+            "#,
+        )
+        .unwrap();
+
+        let mut settings = config
+            .file_settings_for(Path::new("src/generated/deep/mod.rs"))
+            .unwrap();
+        settings.merge(&Settings {
+            max_retries: Some(5),
+            ..Default::default()
+        });
+
+        assert_eq!(settings.model(), "o1-preview");
+        assert_eq!(settings.max_retries(), 5);
+        assert_eq!(
+            settings.timeout(),
+            Duration::from_secs(DEFAULT_TIMEOUT_SECS)
+        );
+        assert_eq!(
+            settings.headers.as_deref(),
+            Some("OpenAI-Organization: org-1; OpenAI-Project: proj-1")
+        );
+
+        let settings = config.file_settings_for(Path::new("src/main.rs")).unwrap();
+        assert_eq!(settings.model(), "gpt-4o-mini");
+        assert_eq!(settings.timeout(), Duration::from_secs(10));
+    }
+}
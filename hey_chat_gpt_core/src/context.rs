@@ -0,0 +1,288 @@
+//! `context = "crate"` のときに送る、クレート全体の情報
+//!
+//! 1ファイルだけを見て存在しない依存クレートを使ったり、既にあるヘルパーを作り直したりしないよう、
+//! `Cargo.toml` の依存関係・エディションと、他のモジュールの公開アイテムのシグネチャを渡す
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use syn::{Fields, ImplItem, Item, TraitItem, Visibility};
+
+use super::config::{manifest_dir, relative_to_manifest_dir, CrateInfo};
+use super::exclude::strip_private;
+use super::ide::is_ide_expansion;
+use super::tokenizer::count_tokens;
+
+/// `source_file_path` 以外のモジュールについてのコンテキストを `max_tokens` に収まるように組み立てる
+///
+/// キャッシュのキーにも使うので呼び出しのたびに必要になる。1回のビルド (rustcのプロセス) の中ではクレートは
+/// 変わらないので、組み立てた結果を使い回す。IDEのプロセスは編集の間も動き続けるので使い回さない
+pub fn crate_context(source_file_path: &Path, max_tokens: u64) -> String {
+    static BUILT: OnceLock<Mutex<HashMap<(PathBuf, u64), String>>> = OnceLock::new();
+
+    if is_ide_expansion() {
+        return build_crate_context(source_file_path, max_tokens);
+    }
+    let built = BUILT.get_or_init(Default::default);
+    let key = (source_file_path.to_path_buf(), max_tokens);
+    if let Some(context) = built.lock().unwrap().get(&key) {
+        return context.clone();
+    }
+    let context = build_crate_context(source_file_path, max_tokens);
+    built.lock().unwrap().insert(key, context.clone());
+    context
+}
+
+fn build_crate_context(source_file_path: &Path, max_tokens: u64) -> String {
+    let crate_info = CrateInfo::load();
+    let current = relative_to_manifest_dir(source_file_path);
+    let modules = find_modules(&manifest_dir().join("src"))
+        .into_iter()
+        .filter(|(path, _)| relative_to_manifest_dir(path) != current)
+        .filter_map(|(path, module_path)| {
+            let content = fs::read_to_string(&path).ok()?;
+            let summary = summarize(&strip_private(&content))?;
+            Some(format!("// {}\n{}", module_path, summary))
+        });
+
+    let mut context = format!(
+        "Context of the crate `{}` (edition {}). Use only the dependencies listed here, and reuse the existing items below instead of redefining them. Function bodies are omitted.\n",
+        crate_info.name, crate_info.edition,
+    );
+    context.push_str("\n```toml\n");
+    context.push_str(&crate_info.dependencies);
+    context.push_str("```\n");
+
+    // 収まらないモジュールは丸ごと省く
+    let mut sections = Vec::new();
+    let mut omitted = 0;
+    let mut tokens = count_tokens(&context);
+    for module in modules {
+        let module_tokens = count_tokens(&module);
+        if tokens + module_tokens > max_tokens {
+            omitted += 1;
+            continue;
+        }
+        tokens += module_tokens;
+        sections.push(module);
+    }
+    if !sections.is_empty() {
+        context.push_str("\n```rust\n");
+        context.push_str(&sections.join("\n"));
+        context.push_str("```\n");
+    }
+    if omitted > 0 {
+        context.push_str(&format!(
+            "\n({} more module(s) omitted to fit the budget)\n",
+            omitted
+        ));
+    }
+
+    context
+}
+
+/// `src` 以下の .rs ファイルとそのモジュールパス (`src/bin` は別のクレートなので除く)
+fn find_modules(src_dir: &Path) -> Vec<(PathBuf, String)> {
+    let mut modules = Vec::new();
+    let mut dirs = vec![src_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if path.is_dir() {
+                if path != src_dir.join("bin") {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                let module_path = module_path(path.strip_prefix(src_dir).unwrap());
+                modules.push((path, module_path));
+            }
+        }
+    }
+
+    modules.sort();
+    modules
+}
+
+/// `foo/bar.rs`, `foo/bar/mod.rs` → `crate::foo::bar`
+fn module_path(relative: &Path) -> String {
+    let mut segments = vec!["crate".to_string()];
+    segments.extend(
+        relative
+            .with_extension("")
+            .iter()
+            .map(|segment| segment.to_string_lossy().to_string()),
+    );
+    if matches!(
+        segments.last().map(String::as_str),
+        Some("mod" | "lib" | "main")
+    ) {
+        segments.pop();
+    }
+    segments.join("::")
+}
+
+/// 公開アイテムのシグネチャだけを残したソース (何もなければ `None`)
+fn summarize(content: &str) -> Option<String> {
+    let mut file = syn::parse_file(content).ok()?;
+    file.attrs.clear();
+    file.items = public_items(file.items);
+    if file.items.is_empty() {
+        return None;
+    }
+    Some(prettyplease::unparse(&file))
+}
+
+fn is_visible(vis: &Visibility) -> bool {
+    !matches!(vis, Visibility::Inherited)
+}
+
+fn public_items(items: Vec<Item>) -> Vec<Item> {
+    items
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::Fn(mut item) if is_visible(&item.vis) => {
+                item.block.stmts.clear();
+                Some(Item::Fn(item))
+            }
+            Item::Struct(mut item) if is_visible(&item.vis) => {
+                if let Fields::Named(fields) = &mut item.fields {
+                    fields.named = fields
+                        .named
+                        .clone()
+                        .into_iter()
+                        .filter(|field| is_visible(&field.vis))
+                        .collect();
+                }
+                Some(Item::Struct(item))
+            }
+            Item::Enum(item) if is_visible(&item.vis) => Some(Item::Enum(item)),
+            Item::Type(item) if is_visible(&item.vis) => Some(Item::Type(item)),
+            Item::Const(mut item) if is_visible(&item.vis) => {
+                // 値は省く
+                item.expr = Box::new(syn::parse_quote!(..));
+                Some(Item::Const(item))
+            }
+            Item::Static(mut item) if is_visible(&item.vis) => {
+                item.expr = Box::new(syn::parse_quote!(..));
+                Some(Item::Static(item))
+            }
+            Item::Trait(mut item) if is_visible(&item.vis) => {
+                for trait_item in &mut item.items {
+                    if let TraitItem::Fn(trait_fn) = trait_item {
+                        trait_fn.default = None;
+                        trait_fn.semi_token = Some(Default::default());
+                    }
+                }
+                Some(Item::Trait(item))
+            }
+            Item::Impl(mut item) => {
+                item.items.retain_mut(|impl_item| match impl_item {
+                    ImplItem::Fn(impl_fn) => {
+                        impl_fn.block.stmts.clear();
+                        // トレイト実装のメソッドは可視性を持たない
+                        item.trait_.is_some() || is_visible(&impl_fn.vis)
+                    }
+                    ImplItem::Const(impl_const) => {
+                        item.trait_.is_some() || is_visible(&impl_const.vis)
+                    }
+                    ImplItem::Type(_) => true,
+                    _ => false,
+                });
+                (item.trait_.is_some() || !item.items.is_empty()).then_some(Item::Impl(item))
+            }
+            Item::Mod(mut item) if is_visible(&item.vis) => {
+                if let Some((_, content)) = item.content.take() {
+                    item.content = Some((Default::default(), public_items(content)));
+                }
+                Some(Item::Mod(item))
+            }
+            Item::Use(item) if is_visible(&item.vis) => Some(Item::Use(item)),
+            Item::Macro(item) if item.ident.is_some() => {
+                // macro_rules! は名前だけ分かれば良い
+                let ident = item.ident;
+                Some(syn::parse_quote!(macro_rules! #ident { (..) => {}; }))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_path() {
+        assert_eq!(module_path(Path::new("lib.rs")), "crate");
+        assert_eq!(module_path(Path::new("impls/mod.rs")), "crate::impls");
+        assert_eq!(
+            module_path(Path::new("impls/cache.rs")),
+            "crate::impls::cache"
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize(
+            r#"
+            //! module docs
+            use std::fmt;
+
+            /// Adds one.
+            pub fn add_one(n: u32) -> u32 {
+                n + 1
+            }
+
+            fn private() {}
+
+            pub struct Point {
+                pub x: i32,
+                secret: i32,
+            }
+
+            impl Point {
+                pub fn new() -> Self {
+                    Self { x: 0, secret: 0 }
+                }
+
+                fn hidden(&self) {}
+            }
+
+            impl fmt::Display for Point {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "{}", self.x)
+                }
+            }
+
+            pub trait Shape {
+                fn area(&self) -> f64 {
+                    0.0
+                }
+            }
+
+            pub const LIMIT: usize = 10;
+            "#,
+        )
+        .unwrap();
+
+        assert!(summary.contains("/// Adds one.\npub fn add_one(n: u32) -> u32 {}"));
+        assert!(summary.contains("pub x: i32"));
+        assert!(!summary.contains("secret"));
+        assert!(!summary.contains("private"));
+        assert!(!summary.contains("hidden"));
+        assert!(!summary.contains("n + 1"));
+        assert!(summary.contains("pub fn new() -> Self {}"));
+        assert!(summary.contains("fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {}"));
+        assert!(summary.contains("fn area(&self) -> f64;"));
+        assert!(summary.contains("pub const LIMIT: usize = ..;"));
+        assert!(!summary.contains("use std::fmt"));
+
+        assert_eq!(summarize("fn main() {}"), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! 生成されたコードを読める `.rs` ファイルに書き出す
+//!
+//! アイテムの位置の呼び出しは `include!` に展開されるので、コンパイルエラーやパニックの位置が生成されたコードの行を指す
+
+use anyhow::Context;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::cache::get_state_dir;
+use super::config::relative_to_manifest_dir;
+use super::util::write_atomic;
+
+/// `<キャッシュディレクトリ>/<ソースファイルのディレクトリ>/<ファイル名>_<入力のハッシュの先頭12文字>.rs`
+///
+/// 行番号は古いコンパイラでは分からないので、同じファイルの呼び出しはマクロの入力で区別する
+pub fn emitted_file_path(source_file_path: &Path, input_hash: &str) -> PathBuf {
+    let relative = relative_to_manifest_dir(source_file_path);
+    // `<anon>` のようなパスやクレートの外のファイルでもキャッシュディレクトリの外に出ないようにする
+    let dir = relative
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect::<PathBuf>();
+    let stem = relative
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .replace(|c: char| !c.is_alphanumeric() && c != '_' && c != '-', "_");
+
+    get_state_dir().join(dir).join(format!(
+        "{}_{}.rs",
+        stem,
+        &input_hash[..input_hash.len().min(12)]
+    ))
+}
+
+/// 書き出したファイルのパスを返す。内容が変わらなければ書き直さない (再ビルドを避けるため)
+pub fn emit_file(source_file_path: &Path, input_hash: &str, code: &str) -> anyhow::Result<PathBuf> {
+    let path = emitted_file_path(source_file_path, input_hash);
+    let content = format!(
+        "// Generated by hey_chat_gpt for {}. Edits are overwritten when the code is regenerated.\n\n{}\n",
+        relative_to_manifest_dir(source_file_path).display(),
+        code.trim_end()
+    );
+    if fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+        return Ok(path);
+    }
+
+    // 並行するrustcが `include!` で書きかけのファイルを読まないようにする
+    write_atomic(&path, &content).with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emitted_file_path() {
+        // キャッシュのテストが消すディレクトリを同時に作らない
+        let _lock = crate::config::TEST_ENV_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let state_dir = get_state_dir();
+        let hash = "0123456789abcdef0123";
+        assert_eq!(
+            emitted_file_path(Path::new("src/impls/mod.rs"), hash),
+            state_dir.join("src/impls/mod_0123456789ab.rs")
+        );
+        assert_eq!(
+            emitted_file_path(Path::new("<anon>"), hash),
+            state_dir.join("_anon__0123456789ab.rs")
+        );
+        assert_eq!(
+            emitted_file_path(Path::new("/tmp/../outside/main.rs"), hash),
+            state_dir.join("tmp/outside/main_0123456789ab.rs")
+        );
+        // 作っただけのディレクトリは残さない
+        let _ = fs::remove_dir(&state_dir);
+    }
+}
@@ -0,0 +1,287 @@
+//! プロンプトに含めない部分を取り除く
+//!
+//! - `// hey_gpt:ignore-start` から `// hey_gpt:ignore-end` までの行は丸ごと省く
+//! - `#[hey_gpt::private]` (または `#[hey_chat_gpt::private]`) の付いたアイテムは本体を省いてシグネチャだけを残す
+//!
+//! キャッシュのキーも取り除いた後の内容から作るので、省いた部分を変更しても再生成されない
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+const IGNORE_START: &str = "// hey_gpt:ignore-start";
+const IGNORE_END: &str = "// hey_gpt:ignore-end";
+const OMITTED_BODY: &str = "{ /* private */ }";
+
+pub fn strip_private(content: &str) -> String {
+    strip_private_items(&strip_ignored_lines(content))
+}
+
+fn strip_ignored_lines(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut ignored = None;
+    for line in content.split_inclusive('\n') {
+        match ignored {
+            None if line.trim_start().starts_with(IGNORE_START) => ignored = Some(0),
+            None => stripped.push_str(line),
+            Some(count) if line.trim_start().starts_with(IGNORE_END) => {
+                stripped.push_str(&format!("// ({} lines omitted)\n", count));
+                ignored = None;
+            }
+            Some(count) => ignored = Some(count + 1),
+        }
+    }
+    // 閉じられていなければ最後まで省く
+    if let Some(count) = ignored {
+        stripped.push_str(&format!("// ({} lines omitted)\n", count));
+    }
+    stripped
+}
+
+fn private_attribute() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"(?m)^[ \t]*#\[\s*(?:hey_gpt|hey_chat_gpt)\s*::\s*private\s*\]\s*").unwrap()
+    })
+}
+
+fn strip_private_items(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut last = 0;
+    for attribute in private_attribute().find_iter(content) {
+        // 省いたアイテムの中の属性は無視する
+        if attribute.start() < last {
+            continue;
+        }
+        stripped.push_str(&content[last..attribute.start()]);
+        let item = &content[attribute.end()..];
+        match scan_item(item) {
+            Some(Item::Body { body_start, end }) => {
+                stripped.push_str(&item[..body_start]);
+                stripped.push_str(OMITTED_BODY);
+                last = attribute.end() + end;
+            }
+            Some(Item::Semi { end }) => {
+                stripped.push_str("// (private item omitted)");
+                last = attribute.end() + end;
+            }
+            // 解釈できなければ属性だけ外して残りはそのまま
+            None => last = attribute.end(),
+        }
+    }
+    stripped.push_str(&content[last..]);
+    stripped
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Item {
+    /// `fn f() { ... }` や `struct S { ... }` など。 `{` の位置と `}` の直後
+    Body { body_start: usize, end: usize },
+    /// `const X: u32 = 1;` や `struct S(u32);` など。 `;` の直後
+    Semi { end: usize },
+}
+
+/// アイテムの終わりを探す (文字列・文字・コメント中の括弧は数えない)
+fn scan_item(item: &str) -> Option<Item> {
+    let bytes = item.as_bytes();
+    let mut depth = 0usize;
+    let mut body_start = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i += item[i..].find('\n').unwrap_or(item.len() - i);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_block_comment(item, i)?;
+                continue;
+            }
+            b'"' => {
+                i = skip_string(item, i + 1)?;
+                continue;
+            }
+            b'r' | b'b' if is_raw_string_start(item, i) => {
+                i = skip_raw_string(item, i)?;
+                continue;
+            }
+            b'\'' => {
+                i = skip_char_or_lifetime(item, i);
+                continue;
+            }
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.checked_sub(1)?,
+            b'{' => {
+                if depth == 0 && body_start.is_none() {
+                    body_start = Some(i);
+                }
+                depth += 1;
+            }
+            b'}' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    if let Some(body_start) = body_start {
+                        return Some(Item::Body {
+                            body_start,
+                            end: i + 1,
+                        });
+                    }
+                }
+            }
+            b';' if depth == 0 => return Some(Item::Semi { end: i + 1 }),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn skip_block_comment(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < text.len() {
+        match &text.as_bytes()[i..i + 2] {
+            b"/*" => {
+                depth += 1;
+                i += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// `"` の直後から閉じる `"` の直後まで
+fn skip_string(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn is_raw_string_start(text: &str, i: usize) -> bool {
+    // 識別子の途中 (`bar"` 等) ではないこと
+    let preceded_by_ident = text[..i]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let rest = text[i..].strip_prefix('b').unwrap_or(&text[i..]);
+    !preceded_by_ident
+        && rest
+            .strip_prefix('r')
+            .is_some_and(|rest| rest.trim_start_matches('#').starts_with('"'))
+}
+
+fn skip_raw_string(text: &str, start: usize) -> Option<usize> {
+    let quote = start + text[start..].find('"')?;
+    let hashes = &text[start..quote].trim_start_matches(['b', 'r']);
+    let terminator = format!("\"{}", hashes);
+    let end = text[quote + 1..].find(&terminator)?;
+    Some(quote + 1 + end + terminator.len())
+}
+
+/// `'a'` や `'\n'` は読み飛ばし、 `'a` (ライフタイム) は `'` だけ進める
+fn skip_char_or_lifetime(text: &str, start: usize) -> usize {
+    let rest = &text[start + 1..];
+    let mut chars = rest.char_indices();
+    match chars.next() {
+        // エスケープされた文字の次から閉じる `'` を探す
+        Some((_, '\\')) => match rest.get(2..).and_then(|rest| rest.find('\'')) {
+            Some(end) => start + 1 + 2 + end + 1,
+            None => start + 1,
+        },
+        Some(_) => match chars.next() {
+            Some((i, '\'')) => start + 1 + i + 1,
+            _ => start + 1,
+        },
+        None => start + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_markers() {
+        let content = "fn a() {}\n// hey_gpt:ignore-start\nfn secret() {}\nfn secret2() {}\n    // hey_gpt:ignore-end\nfn b() {}\n";
+        assert_eq!(
+            strip_private(content),
+            "fn a() {}\n// (2 lines omitted)\nfn b() {}\n"
+        );
+
+        let content = "fn a() {}\n// hey_gpt:ignore-start\nfn secret() {}\n";
+        assert_eq!(strip_private(content), "fn a() {}\n// (1 lines omitted)\n");
+    }
+
+    #[test]
+    fn test_private_items() {
+        let content = r##"use hey_chat_gpt as hey_gpt;
+
+#[hey_gpt::private]
+/// Licensed algorithm.
+pub fn secret<'a>(s: &'a str) -> Vec<&'a str> {
+    let close = '}';
+    let text = "}{ not a brace";
+    let raw = r#"}"#;
+    // }
+    /* { */
+    s.split(close).collect()
+}
+
+#[hey_chat_gpt::private]
+pub const KEY: &str = "};";
+
+#[hey_gpt::private]
+impl Foo {
+    fn bar(&self) {}
+}
+
+fn visible() {}
+"##;
+        let stripped = strip_private(content);
+
+        assert_eq!(
+            stripped,
+            r#"use hey_chat_gpt as hey_gpt;
+
+/// Licensed algorithm.
+pub fn secret<'a>(s: &'a str) -> Vec<&'a str> { /* private */ }
+
+// (private item omitted)
+
+impl Foo { /* private */ }
+
+fn visible() {}
+"#
+        );
+    }
+
+    #[test]
+    fn test_scan_item() {
+        assert_eq!(
+            scan_item("struct S(u32);\nfn f() {}"),
+            Some(Item::Semi { end: 14 })
+        );
+        assert_eq!(
+            scan_item("fn f() -> [u8; 2] { [0; 2] } fn g() {}"),
+            Some(Item::Body {
+                body_start: 18,
+                end: 28
+            })
+        );
+        assert_eq!(scan_item("fn f() {"), None);
+    }
+}
@@ -0,0 +1,8 @@
+fn helper() -> u32 {
+    42
+}
+// ----
+#[test]
+fn helper_works() {
+    assert_eq!(helper(), 42);
+}
@@ -0,0 +1,4 @@
+/// Returns the greeting message.
+fn greet() -> &'static str {
+    "Hello"
+}
@@ -0,0 +1,8 @@
+/// Parses a number.
+///
+/// ```
+/// assert_eq!(parse("1"), Some(1));
+/// ```
+fn parse(s: &str) -> Option<i32> {
+    s.parse().ok()
+}
@@ -0,0 +1,3 @@
+fn roll() -> u8 {
+    4 // chosen by fair dice roll
+}
@@ -0,0 +1,10 @@
+struct Point {
+    x: f64,
+    y: f64,
+}
+// ----
+impl Point {
+    fn norm(&self) -> f64 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+}
@@ -0,0 +1,6 @@
+fn fib(n: usize) -> usize {
+    match n {
+        0 | 1 => n,
+        _ => fib(n - 1) + fib(n - 2),
+    }
+}
@@ -0,0 +1,3 @@
+fn square(x: i64) -> i64 {
+    x * x
+}
@@ -0,0 +1,7 @@
+fn one() -> u8 {
+    1
+}
+// ----
+fn two() -> u8 {
+    2
+}
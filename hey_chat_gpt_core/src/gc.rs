@@ -0,0 +1,350 @@
+//! キャッシュの整理
+//!
+//! 設定ファイルの `max_age` / `max_entries` / `max_bytes` を超えた分を、最後に使われたのが古いものから消す。
+//! `keep_latest_only` では呼び出しごとに最新のもの以外を消す。
+//! `load_cache` から1時間に1回まで行うほか、 `cargo hey-gpt gc` でも行える
+
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::cache::{get_cache_dir, load_entry_meta, meta_path, unix_now, EntryMeta};
+use super::config::Config;
+use super::util::{lock_file, write_atomic};
+
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const GC_STAMP_FILE_NAME: &str = "gc.stamp";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_age: Option<Duration>,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub keep_latest_only: bool,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            max_age: config
+                .max_age
+                .as_deref()
+                .map(parse_duration)
+                .transpose()
+                .context("invalid `max_age`")?,
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            keep_latest_only: config.keep_latest_only,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `"90"` (秒)、 `"90s"`、 `"30m"`、 `"12h"`、 `"30d"`
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let Ok(number) = number.parse::<u64>() else {
+        bail!("`{}` is not a duration such as \"30d\" or \"12h\"", value);
+    };
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("unknown unit in `{}` (expected s, m, h or d)", value),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// キャッシュのエントリ (応答と、あれば会話履歴と情報のファイル)
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub cache_file: PathBuf,
+    pub meta: EntryMeta,
+    pub bytes: u64,
+}
+
+impl Entry {
+    fn load(key: String, cache_file: PathBuf) -> Self {
+        let meta = load_entry_meta(&cache_file);
+        let bytes = entry_files(&cache_file)
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        Self {
+            key,
+            cache_file,
+            meta,
+            bytes,
+        }
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        for path in entry_files(&self.cache_file) {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err).with_context(|| format!("failed to remove {}", path.display()))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn entry_files(cache_file: &Path) -> [PathBuf; 3] {
+    [
+        cache_file.to_path_buf(),
+        cache_file.with_extension("transcript.json"),
+        meta_path(cache_file),
+    ]
+}
+
+/// `objects/` 以下のエントリと、以前の形式の `cache_<ハッシュ>.txt`
+pub fn list_entries(cache_dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![cache_dir.to_path_buf()];
+    if let Ok(objects) = fs::read_dir(cache_dir.join("objects")) {
+        dirs.extend(objects.flatten().map(|entry| entry.path()));
+    }
+
+    for dir in dirs {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in read_dir.flatten().map(|entry| entry.path()) {
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // 会話履歴などは `<キー>.transcript.json` のように拡張子が2つある
+            let Some(key) = file_name.strip_suffix(".txt") else {
+                continue;
+            };
+            let key = match dir == cache_dir {
+                true => match key.strip_prefix("cache_") {
+                    Some(key) => key,
+                    None => continue,
+                },
+                false if key.contains('.') || key.starts_with('.') => continue,
+                false => key,
+            };
+            entries.push(Entry::load(key.to_string(), path.clone()));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// 消すエントリの位置を返す。 `protect` のキーのエントリは消さない (これから使う)
+pub fn select_evictions(
+    entries: &[Entry],
+    limits: &Limits,
+    now: u64,
+    protect: Option<&str>,
+) -> Vec<usize> {
+    let mut evict = vec![false; entries.len()];
+
+    if limits.keep_latest_only {
+        // 同じファイルでもマクロや設定が違う呼び出しは別のキーになるので、呼び出しごとに比べる。
+        // どの呼び出しのものか分からないエントリ (以前のキャッシュなど) は消さない
+        let group = |entry: &Entry| {
+            let source = entry.meta.source.clone()?;
+            Some((source, entry.meta.caller.clone()?))
+        };
+        let mut latest = HashMap::new();
+        for entry in entries {
+            if let Some(group) = group(entry) {
+                let created = latest.entry(group).or_insert(entry.meta.created);
+                *created = entry.meta.created.max(*created);
+            }
+        }
+        // 同じ秒に作られたものはどちらが新しいか分からないので両方残す
+        for (i, entry) in entries.iter().enumerate() {
+            if group(entry).is_some_and(|group| latest[&group] > entry.meta.created) {
+                evict[i] = true;
+            }
+        }
+    }
+
+    if let Some(max_age) = limits.max_age {
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.meta.accessed.saturating_add(max_age.as_secs()) < now {
+                evict[i] = true;
+            }
+        }
+    }
+
+    // 残ったものを最近使われた順に数え、上限を超えた分を消す
+    let mut order = (0..entries.len())
+        .filter(|&i| !evict[i])
+        .collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(entries[i].meta.accessed));
+    let (mut count, mut bytes) = (0, 0);
+    for i in order {
+        count += 1;
+        bytes += entries[i].bytes;
+        if limits.max_entries.is_some_and(|max| count > max)
+            || limits.max_bytes.is_some_and(|max| bytes > max)
+        {
+            evict[i] = true;
+        }
+    }
+
+    (0..entries.len())
+        .filter(|&i| evict[i] && Some(entries[i].key.as_str()) != protect)
+        .collect()
+}
+
+/// 整理して、消した (`dry_run` では消すはずの) エントリを返す
+pub fn collect_garbage(
+    limits: &Limits,
+    protect: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Entry>> {
+    let cache_dir = get_cache_dir();
+    let entries = list_entries(&cache_dir)?;
+    let evicted = select_evictions(&entries, limits, unix_now(), protect)
+        .into_iter()
+        .map(|i| entries[i].clone())
+        .collect::<Vec<_>>();
+    if !dry_run {
+        for entry in &evicted {
+            entry.remove()?;
+        }
+    }
+    Ok(evicted)
+}
+
+/// 上限が設定されていて、前回の整理から1時間以上経っていれば整理する
+pub fn collect_if_due(protect: &str) -> anyhow::Result<()> {
+    let limits = Limits::from_config(&Config::load()?)?;
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let cache_dir = get_cache_dir();
+    let stamp = cache_dir.join(GC_STAMP_FILE_NAME);
+    let is_due = || {
+        fs::metadata(&stamp)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|elapsed| elapsed >= GC_INTERVAL)
+    };
+    if !is_due() {
+        return Ok(());
+    }
+    // 同時に整理を始めたプロセスは、先のプロセスが終わってから確かめ直す
+    let _lock = lock_file(&cache_dir.join("locks").join("gc.lock"))?;
+    if !is_due() {
+        return Ok(());
+    }
+
+    collect_garbage(&limits, Some(protect), false)?;
+    write_atomic(&stamp, &unix_now().to_string())
+        .with_context(|| format!("failed to write {}", stamp.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, source: Option<&str>, created: u64, accessed: u64, bytes: u64) -> Entry {
+        Entry {
+            key: key.to_string(),
+            cache_file: PathBuf::from(format!("{}.txt", key)),
+            meta: EntryMeta {
+                source: source.map(str::to_string),
+                caller: Some("caller".to_string()),
+                model: None,
+                created,
+                accessed,
+            },
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_select_evictions() {
+        let entries = vec![
+            entry("a", Some("/crate/src/main.rs"), 10, 100, 10),
+            entry("b", Some("/crate/src/main.rs"), 20, 50, 10),
+            entry("c", Some("/crate/src/lib.rs"), 10, 90, 10),
+            entry("d", None, 5, 5, 10),
+            entry("e", Some("/crate/src/lib.rs"), 10, 80, 0),
+        ];
+        let day = 24 * 60 * 60;
+
+        let limits = Limits {
+            keep_latest_only: true,
+            ..Limits::default()
+        };
+        // 同じ時刻に作られたもの (c と e) は両方残す
+        assert_eq!(select_evictions(&entries, &limits, 100, None), vec![0]);
+
+        let limits = Limits {
+            max_age: Some(Duration::from_secs(day)),
+            ..Limits::default()
+        };
+        assert_eq!(
+            select_evictions(&entries, &limits, day + 60, None),
+            vec![1, 3]
+        );
+
+        // 最後に使われたのが古いものから消す
+        let limits = Limits {
+            max_entries: Some(2),
+            ..Limits::default()
+        };
+        assert_eq!(
+            select_evictions(&entries, &limits, 100, None),
+            vec![1, 3, 4]
+        );
+        let limits = Limits {
+            max_bytes: Some(25),
+            ..Limits::default()
+        };
+        assert_eq!(select_evictions(&entries, &limits, 100, None), vec![1, 3]);
+        assert_eq!(select_evictions(&entries, &limits, 100, Some("d")), vec![1]);
+
+        // 同じファイルの `do_it!` と `あとは任せた!` は別の呼び出しとしてそれぞれ最新のものを残す。
+        // どの呼び出しのものか分からないもの (以前のキャッシュ) は消さない
+        let mut entries = vec![
+            entry("a", Some("/crate/src/main.rs"), 10, 100, 10),
+            entry("b", Some("/crate/src/main.rs"), 20, 100, 10),
+            entry("c", Some("/crate/src/main.rs"), 30, 100, 10),
+            entry("d", Some("/crate/src/main.rs"), 5, 100, 10),
+        ];
+        entries[1].meta.caller = Some("other caller".to_string());
+        entries[3].meta.caller = None;
+        let limits = Limits {
+            keep_latest_only: true,
+            ..Limits::default()
+        };
+        assert_eq!(select_evictions(&entries, &limits, 100, None), vec![0]);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("30d").unwrap(),
+            Duration::from_secs(30 * 24 * 60 * 60)
+        );
+        assert!(parse_duration("a week").is_err());
+        assert!(parse_duration("3w").is_err());
+    }
+}
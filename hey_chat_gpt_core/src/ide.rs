@@ -0,0 +1,130 @@
+//! rust-analyzer などIDEでの展開
+//!
+//! IDEはキー入力のたびにマクロを展開するので、APIには問い合わせずキャッシュだけを使う。
+//! キャッシュがなければ前回の生成 (または `signatures { ... }`) から関数の中身を省いたスタブを返す
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::env;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_quote, Attribute, ImplItem, Item, ItemFn, Signature, Token, Visibility};
+
+/// `HEY_GPT_IDE` が指定されていればそれに従い、なければrust-analyzerのproc-macroサーバーかどうかで判断する
+pub fn is_ide_expansion() -> bool {
+    match env::var("HEY_GPT_IDE") {
+        Ok(value) => !matches!(value.as_str(), "" | "0" | "false"),
+        Err(_) => env::var_os("RUST_ANALYZER_INTERNALS_DO_NOT_USE").is_some(),
+    }
+}
+
+/// 関数の中身を `unimplemented!()` にしたものを返す (補完や定義へのジャンプに使えればよい)
+pub fn stub(tokens: TokenStream) -> TokenStream {
+    let Ok(file) = syn::parse2::<syn::File>(tokens) else {
+        return TokenStream::new();
+    };
+    let items = file.items.into_iter().map(stub_item);
+    quote! { #(#items)* }
+}
+
+fn stub_item(item: Item) -> Item {
+    match item {
+        Item::Fn(mut item) => {
+            stub_body(&mut item.attrs, &mut item.block);
+            Item::Fn(item)
+        }
+        Item::Impl(mut item) => {
+            for impl_item in &mut item.items {
+                if let ImplItem::Fn(impl_item) = impl_item {
+                    stub_body(&mut impl_item.attrs, &mut impl_item.block);
+                }
+            }
+            Item::Impl(item)
+        }
+        Item::Mod(mut item) => {
+            if let Some((_, items)) = &mut item.content {
+                *items = std::mem::take(items).into_iter().map(stub_item).collect();
+            }
+            Item::Mod(item)
+        }
+        // `fn f() -> u32;` のような中身のない関数
+        Item::Verbatim(tokens) => match syn::parse2::<BodilessFn>(tokens.clone()) {
+            Ok(BodilessFn { attrs, vis, sig }) => stub_item(Item::Fn(ItemFn {
+                attrs,
+                vis,
+                sig,
+                block: Box::new(parse_quote!({})),
+            })),
+            Err(_) => Item::Verbatim(tokens),
+        },
+        item => item,
+    }
+}
+
+fn stub_body(attrs: &mut Vec<Attribute>, block: &mut syn::Block) {
+    attrs.push(parse_quote!(#[allow(unused_variables)]));
+    *block = parse_quote!({ unimplemented!() });
+}
+
+struct BodilessFn {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    sig: Signature,
+}
+
+impl Parse for BodilessFn {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let sig = input.parse()?;
+        input.parse::<Token![;]>()?;
+        Ok(Self { attrs, vis, sig })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stub() {
+        let tokens = quote! {
+            use std::collections::HashMap;
+
+            /// n番目のフィボナッチ数
+            pub fn fib(n: u64) -> u64 {
+                if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+            }
+
+            pub struct Cache(HashMap<u64, u64>);
+
+            impl Cache {
+                pub fn get(&self, n: u64) -> Option<u64> {
+                    self.0.get(&n).copied()
+                }
+            }
+
+            pub fn declared(n: u64) -> u64;
+        };
+
+        let expected = quote! {
+            use std::collections::HashMap;
+
+            /// n番目のフィボナッチ数
+            #[allow(unused_variables)]
+            pub fn fib(n: u64) -> u64 { unimplemented!() }
+
+            pub struct Cache(HashMap<u64, u64>);
+
+            impl Cache {
+                #[allow(unused_variables)]
+                pub fn get(&self, n: u64) -> Option<u64> { unimplemented!() }
+            }
+
+            #[allow(unused_variables)]
+            pub fn declared(n: u64) -> u64 { unimplemented!() }
+        };
+        assert_eq!(stub(tokens).to_string(), expected.to_string());
+
+        assert!(stub(quote! { not rust code }).is_empty());
+    }
+}
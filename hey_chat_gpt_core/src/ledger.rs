@@ -0,0 +1,301 @@
+//! API呼び出しごとのトークン数と費用の記録
+//!
+//! キャッシュディレクトリの `ledger.jsonl` に1呼び出し1行で追記していく。
+//! 予算を設定した場合、送信中のリクエストの見積もりは `reservations/` に置き、記録し終えたら消す
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::cache::get_state_dir;
+use super::config::{relative_to_manifest_dir, Config};
+use super::query::Usage;
+use super::util::{lock_file, write_atomic};
+
+const LEDGER_FILE_NAME: &str = "ledger.jsonl";
+const RESERVATIONS_DIR_NAME: &str = "reservations";
+/// これより古い予約は、予約したプロセスが記録せずに終わったものとして数えない
+const RESERVATION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// 100万トークンあたりの料金 (USD)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// モデル名の前方一致と100万トークンあたりの入力・出力の料金 (最も長く一致したものを使う)
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("o1", 15.0, 60.0),
+    ("o1-preview", 15.0, 60.0),
+    ("o1-mini", 3.0, 12.0),
+    ("o3-mini", 1.1, 4.4),
+];
+
+/// `max_completion_tokens` がない時に見込む返答のトークン数 (モデル名の前方一致、最も長く一致したものを使う)。
+/// 推論モデルは見えない推論の分も出力として払うので多めに見込む
+const COMPLETION_ESTIMATES: &[(&str, u64)] =
+    &[("", 4_096), ("o1", 16_384), ("o3", 16_384), ("o4", 16_384)];
+
+pub fn estimated_completion_tokens(model: &str) -> u64 {
+    COMPLETION_ESTIMATES
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(0, |(_, tokens)| *tokens)
+}
+
+/// 設定ファイルの `[pricing]` を優先して料金を探す
+pub fn price_for(model: &str, pricing: &BTreeMap<String, Price>) -> Option<Price> {
+    let custom = pricing
+        .iter()
+        .map(|(prefix, price)| (prefix.as_str(), *price));
+    PRICES
+        .iter()
+        .map(|(prefix, input, output)| {
+            (
+                *prefix,
+                Price {
+                    input: *input,
+                    output: *output,
+                },
+            )
+        })
+        .filter(|(prefix, _)| !pricing.contains_key(*prefix))
+        .chain(custom)
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| price)
+}
+
+impl Price {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input + completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// 1回の呼び出しの記録
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// UNIX時刻 (秒)
+    pub timestamp: u64,
+    /// マニフェストディレクトリからの相対パス
+    pub file: String,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// 料金が分からないモデルでは `None`
+    pub cost_usd: Option<f64>,
+}
+
+pub fn ledger_path() -> PathBuf {
+    get_state_dir().join(LEDGER_FILE_NAME)
+}
+
+pub fn load_ledger() -> anyhow::Result<Vec<LedgerEntry>> {
+    let path = ledger_path();
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: invalid entry", path.display(), i + 1))
+        })
+        .collect()
+}
+
+pub fn total_spent(entries: &[LedgerEntry]) -> f64 {
+    // 空の `sum` は -0.0 になるので、0.0 から足していく
+    entries
+        .iter()
+        .filter_map(|entry| entry.cost_usd)
+        .fold(0.0, |sum, cost| sum + cost)
+}
+
+/// 送信中のリクエストの見積もり。 [`record`] し終えてから落とすと予約が消える
+#[derive(Debug)]
+pub struct Reservation {
+    path: PathBuf,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// 他のプロセスやスレッドが送信中のリクエストの見積もりの合計
+fn total_reserved(dir: &Path) -> f64 {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return 0.0;
+    };
+    read_dir
+        .flatten()
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|elapsed| elapsed < RESERVATION_TTL)
+        })
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|cost| cost.trim().parse::<f64>().ok())
+        .fold(0.0, |sum, cost| sum + cost)
+}
+
+/// `budget_usd` が設定されていれば、この呼び出しの見積もりを予約する。超えてしまう場合はエラーにする
+///
+/// `estimated_cost` は送信前に見積もった費用、 `key` は送信中のリクエストのキャッシュのキー。
+/// 並列に送る (`prefetch -j N` など) リクエストが同じ残額で確かめて合わせて超えないよう、
+/// 確かめてから予約するまで台帳のロックを持ち、送信中の他のリクエストの予約も使った額に含める
+pub fn reserve_budget(
+    config: &Config,
+    key: &str,
+    estimated_cost: f64,
+) -> anyhow::Result<Option<Reservation>> {
+    let Some(budget) = config.budget_usd else {
+        return Ok(None);
+    };
+    let state_dir = get_state_dir();
+    let _lock = lock_file(&state_dir.join("locks").join("ledger.lock"))?;
+
+    let reservations_dir = state_dir.join(RESERVATIONS_DIR_NAME);
+    let spent = total_spent(&load_ledger()?);
+    let reserved = total_reserved(&reservations_dir);
+    if spent + reserved + estimated_cost > budget {
+        bail!(
+            "the spending budget of ${:.2} would be exceeded (${:.4} spent, ${:.4} reserved by requests in flight, \
+             this request is estimated at ${:.4}). \
+             Cached responses keep working; raise `budget_usd` to send new requests.",
+            budget,
+            spent,
+            reserved,
+            estimated_cost
+        );
+    }
+
+    let path = reservations_dir.join(format!("{}.txt", key));
+    write_atomic(&path, &estimated_cost.to_string())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(Some(Reservation { path }))
+}
+
+pub fn record(
+    source_file_path: &Path,
+    model: &str,
+    usage: &Usage,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let entry = LedgerEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs()),
+        file: relative_to_manifest_dir(source_file_path)
+            .to_string_lossy()
+            .to_string(),
+        model: model.to_string(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        cost_usd: price_for(model, &config.pricing)
+            .map(|price| price.cost(usage.prompt_tokens, usage.completion_tokens)),
+    };
+
+    // 並列に呼ばれても行が混ざらないよう、1行を1回で書き込む
+    let line = format!("{}\n", serde_json::to_string(&entry)?);
+    let path = ledger_path();
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_for() {
+        let pricing = BTreeMap::new();
+        assert_eq!(
+            price_for("gpt-4o-mini-2024-07-18", &pricing),
+            Some(Price {
+                input: 0.15,
+                output: 0.6
+            })
+        );
+        assert_eq!(
+            price_for("gpt-4o-2024-08-06", &pricing),
+            Some(Price {
+                input: 2.5,
+                output: 10.0
+            })
+        );
+        assert_eq!(price_for("my-local-model", &pricing), None);
+
+        let pricing: BTreeMap<String, Price> = toml::from_str(
+            r#"
+            "gpt-4o" = { input = 1.0, output = 2.0 }
+            "my-local" = { input = 0.0, output = 0.0 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            price_for("gpt-4o-2024-08-06", &pricing),
+            Some(Price {
+                input: 1.0,
+                output: 2.0
+            })
+        );
+        assert_eq!(price_for("gpt-4o-mini", &pricing).unwrap().input, 0.15);
+        assert_eq!(price_for("my-local-model", &pricing).unwrap().input, 0.0);
+
+        let cost = price_for("gpt-4o", &BTreeMap::new())
+            .unwrap()
+            .cost(1_000_000, 100_000);
+        assert!((cost - 3.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimated_completion_tokens() {
+        assert_eq!(estimated_completion_tokens("gpt-4o"), 4_096);
+        assert_eq!(estimated_completion_tokens("o3-mini"), 16_384);
+        assert_eq!(estimated_completion_tokens("my-local-model"), 4_096);
+    }
+
+    #[test]
+    fn test_total_reserved() {
+        let dir = std::env::temp_dir().join(format!("hey_gpt_reservations_{}", std::process::id()));
+        assert_eq!(total_reserved(&dir), 0.0);
+
+        let reservation = Reservation {
+            path: dir.join("a.txt"),
+        };
+        write_atomic(&reservation.path, "0.25").unwrap();
+        write_atomic(&dir.join("b.txt"), "0.5").unwrap();
+        assert!((total_reserved(&dir) - 0.75).abs() < 1e-9);
+
+        // 記録し終えたら予約は消える
+        drop(reservation);
+        assert!((total_reserved(&dir) - 0.5).abs() < 1e-9);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
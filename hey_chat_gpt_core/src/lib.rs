@@ -0,0 +1,297 @@
+//! `hey_chat_gpt` のマクロと `cargo hey-gpt` コマンドが共有する実装
+//!
+//! 利用者が直接使うことは想定していないので、APIは予告なく変わる
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::spanned::Spanned;
+
+pub mod audit;
+mod backend;
+pub mod config;
+use config::ContextMode;
+mod context;
+mod emit;
+mod exclude;
+pub mod gc;
+pub use exclude::strip_private;
+mod ide;
+pub mod ledger;
+pub mod locate;
+pub mod pin;
+mod query;
+mod redact;
+mod retry;
+mod tokenizer;
+
+pub mod cache;
+use cache::{
+    cache_result, caller_id, load_cache, load_latest_response, load_legacy_cache, mark_latest,
+};
+
+mod macro_;
+pub use macro_::{IntoSynRes, MacroInput};
+
+pub mod util;
+use util::{extract_rust_codes, ExtractOptions};
+
+mod standalone;
+pub use standalone::standalone_content;
+
+mod request;
+pub use request::{cached_redactions, request_key, Request};
+
+mod system_message;
+pub use system_message::{build_system_message, is_known_macro};
+
+pub fn do_it(input: MacroInput, macro_name: &str) -> syn::Result<TokenStream> {
+    let span = input.vis.span();
+    if !is_known_macro(macro_name) {
+        return Err(syn::Error::new(
+            span,
+            format!("unknown macro `{}`", macro_name),
+        ));
+    }
+    let ide = ide::is_ide_expansion();
+    let located = locate::source_file_path(span, macro_name, &input.tokens);
+    // 設定はファイルの場所で決まるので、分からなければコンパイラの示す名前で代用する
+    let source_file_path = match &located {
+        Ok(Some(path)) => path.clone(),
+        _ => PathBuf::from(span.file()),
+    };
+    let settings = config::resolve(&source_file_path, &input.settings).into_syn(span)?;
+    let extract_options = settings.extract_options();
+    // `include!` は文や式の位置では使えないので、アイテムの位置の呼び出しだけファイルに書き出す
+    let emitted_name = match &located {
+        Ok(Some(path)) => fs::read_to_string(path)
+            .ok()
+            .filter(|content| locate::is_item_invocation(content, macro_name, &input.tokens))
+            .map(|_| pin::input_hash(&input.tokens)),
+        _ => None,
+    };
+
+    // `file_content` は以前の形式のキャッシュのキーになっていたファイル全体
+    let (content, file_content) = match settings.context() {
+        ContextMode::None => (standalone_content(macro_name, &input).into_syn(span)?, None),
+        _ => {
+            let content = located
+                .into_syn(span)?
+                .and_then(|path| fs::read_to_string(path).ok());
+            let Some(content) = content else {
+                if ide {
+                    return Ok(TokenStream::new());
+                }
+                return Err(syn::Error::new(
+                    span,
+                    format!(
+                        "cannot read the source file of this `{}!` ({}). \
+                         In generated code, `include!`d files or doc tests, \
+                         use `context = \"none\"` and describe what to generate in the macro input",
+                        macro_name,
+                        source_file_path.display()
+                    ),
+                ));
+            };
+            // 送らない部分はキャッシュのキーにも含めない
+            (strip_private(&content), Some(content))
+        }
+    };
+
+    // 固定された出力があれば、ファイルが編集されていても生成し直さない
+    if settings.pin() {
+        let lock = pin::Lock::load().into_syn(span)?;
+        let pinned = lock
+            .find(&source_file_path, macro_name, &input.tokens)
+            .into_syn(span)?;
+        if let Some(pinned) = pinned {
+            // ロックファイルはコミットされるので伏せたまま記録してある。キャッシュと同じく今の内容から戻す
+            let pinned = cached_redactions(&settings, &source_file_path, &content)
+                .into_syn(span)?
+                .restore(pinned);
+            if ide {
+                return Ok(file_content2token_stream(&pinned, &extract_options));
+            }
+            return expand_to_file(
+                &pinned,
+                &extract_options,
+                span,
+                &source_file_path,
+                emitted_name.as_deref(),
+            );
+        }
+    }
+
+    let system_message = build_system_message(macro_name, &settings).into_syn(span)?;
+
+    let key = request_key(
+        &input,
+        &settings,
+        &system_message,
+        &source_file_path,
+        &content,
+    );
+    let caller = caller_id(macro_name, &input.tokens);
+    let mut cache = load_cache(&key).into_syn(span)?;
+    // 以前の形式のキャッシュがあれば、新しいキーに移して使う (IDEでの展開では書き込まない)
+    if let (None, Some(file_content), false) = (&cache, &file_content, ide) {
+        if let Some(legacy) = load_legacy_cache(file_content) {
+            // 以前は伏せずに保存していたので、新しいキャッシュと同じく伏せてから移す
+            let legacy = cached_redactions(&settings, &source_file_path, &content)
+                .into_syn(span)?
+                .hide(&legacy);
+            cache_result(&key, &legacy, &source_file_path, &caller, settings.model())
+                .into_syn(span)?;
+            cache = Some(legacy);
+        }
+    }
+
+    if let Some(cache) = cache {
+        // IDEでの展開はビルドではないので記録しない
+        if !ide {
+            mark_latest(&key, &source_file_path, &caller).into_syn(span)?;
+            let config = config::Config::load().into_syn(span)?;
+            audit::Audit::new(&source_file_path, &key, &config)
+                .record_hit(&settings)
+                .into_syn(span)?;
+        }
+        // 秘密の値がロックファイルに入らないよう、伏せたまま固定する
+        if settings.pin() && !ide {
+            pin_output(
+                &source_file_path,
+                macro_name,
+                &input,
+                &cache,
+                &extract_options,
+            )
+            .into_syn(span)?;
+        }
+        // キャッシュには伏せたまま保存されているので、同じ伏せ方をして元の値に戻す
+        let cache = cached_redactions(&settings, &source_file_path, &content)
+            .into_syn(span)?
+            .restore(&cache);
+        if ide {
+            return Ok(file_content2token_stream(&cache, &extract_options));
+        }
+        return expand_to_file(
+            &cache,
+            &extract_options,
+            span,
+            &source_file_path,
+            emitted_name.as_deref(),
+        );
+    }
+
+    // 編集中はファイルの内容が変わるので、IDEではほとんどの場合ここに来る。IDEを止めないよう決して送らない
+    if ide {
+        let stub = match settings.context() {
+            ContextMode::None => input.signatures.iter().cloned().map(ide::stub).collect(),
+            _ => load_latest_response(&source_file_path, &caller)
+                .map(|response| ide::stub(file_content2token_stream(&response, &extract_options)))
+                .unwrap_or_default(),
+        };
+        return Ok(stub);
+    }
+
+    if settings.offline() {
+        return Err(syn::Error::new(
+            span,
+            "no cached response for this file and offline mode is enabled",
+        ));
+    }
+
+    let api_key = std::env::var("OPENAI_API_KEY").into_syn(span)?;
+    if api_key == "DEBUG" {
+        return Ok(TokenStream::new());
+    }
+
+    let pin = settings.pin();
+    let request = Request::new(
+        macro_name,
+        &input,
+        settings,
+        &system_message,
+        source_file_path.clone(),
+        content,
+    )
+    .into_syn(span)?;
+    let redactions = request.redactions.clone();
+    let response = request.send(&api_key).into_syn(span)?;
+    if pin {
+        pin_output(
+            &source_file_path,
+            macro_name,
+            &input,
+            &response,
+            &extract_options,
+        )
+        .into_syn(span)?;
+    }
+    let res_code = redactions.restore(&response);
+
+    expand_to_file(
+        &res_code,
+        &extract_options,
+        span,
+        &source_file_path,
+        emitted_name.as_deref(),
+    )
+}
+
+/// 採用した出力 (取り出したコード) を `hey_gpt.lock` に記録する。 `res_code` は伏せたままの応答
+fn pin_output(
+    source_file_path: &Path,
+    macro_name: &str,
+    input: &MacroInput,
+    res_code: &str,
+    extract_options: &ExtractOptions,
+) -> anyhow::Result<()> {
+    let _guard = pin::guard()?;
+    let mut lock = pin::Lock::load()?;
+    lock.pin(
+        source_file_path,
+        macro_name,
+        &input.tokens,
+        &extract_code(res_code, extract_options),
+    );
+    lock.save()
+}
+
+/// 生成されたコードをファイルに書き出して `include!` に展開する
+///
+/// `input_hash` がなければ (アイテムの位置にない呼び出し)、アイテムとして読めないもの (式や文) と同じくそのまま展開する
+fn expand_to_file(
+    res_code: &str,
+    extract_options: &ExtractOptions,
+    span: Span,
+    source_file_path: &Path,
+    input_hash: Option<&str>,
+) -> syn::Result<TokenStream> {
+    let code = extract_code(res_code, extract_options);
+    let Some(input_hash) = input_hash.filter(|_| syn::parse_file(&code).is_ok()) else {
+        return Ok(file_content2token_stream(res_code, extract_options));
+    };
+
+    let path = emit::emit_file(source_file_path, input_hash, &code).into_syn(span)?;
+    let path = path.to_string_lossy();
+    Ok(quote! { include!(#path); })
+}
+
+pub fn extract_code(res_code: &str, extract_options: &ExtractOptions) -> String {
+    let codes = extract_rust_codes(res_code, extract_options);
+
+    match codes.len() {
+        0 => res_code.to_string(),
+        _ => codes.join("\n"),
+    }
+}
+
+fn file_content2token_stream(res_code: &str, extract_options: &ExtractOptions) -> TokenStream {
+    let res_code = extract_code(res_code, extract_options);
+
+    match res_code.parse() {
+        Ok(ok) => ok,
+        Err(_) => quote! { compile_error!(#res_code); },
+    }
+}
@@ -0,0 +1,233 @@
+//! マクロの呼び出し元のファイルを探す
+//!
+//! Rust 1.88 以降では `Span::local_file` で分かる。
+//! それより古いコンパイラではクレート内の .rs ファイルから同じ呼び出しを探す
+
+use anyhow::bail;
+use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::visit::Visit;
+
+use super::config::manifest_dir;
+use super::is_known_macro;
+
+/// `span` の位置するファイルを返す。見つからなければ `None` (Rust Analyzer など)
+pub fn source_file_path(
+    span: Span,
+    macro_name: &str,
+    tokens: &TokenStream,
+) -> anyhow::Result<Option<PathBuf>> {
+    if let Some(path) = span.local_file() {
+        return Ok(Some(path));
+    }
+
+    let expected = token_strings(tokens.clone());
+    let mut found = Vec::new();
+    for path in find_rust_files(&manifest_dir())? {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if !content.contains(macro_name) {
+            continue;
+        }
+        if find_invocations(&content)
+            .into_iter()
+            .any(|(name, tokens)| name == macro_name && token_strings(tokens) == expected)
+        {
+            found.push(path);
+        }
+    }
+
+    match found.len() {
+        0 | 1 => Ok(found.pop()),
+        _ => bail!(
+            "cannot tell which file this `{}!` is in because the same invocation appears in {} \
+             (use Rust 1.88 or later, or make the invocations differ)",
+            macro_name,
+            found
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// 空白の違いを無視して比べるため、トークンを1つずつ文字列にする
+pub(crate) fn token_strings(tokens: TokenStream) -> Vec<String> {
+    let mut strings = Vec::new();
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                strings.push(open.to_string());
+                strings.extend(token_strings(group.stream()));
+                strings.push(close.to_string());
+            }
+            token => strings.push(token.to_string()),
+        }
+    }
+    strings
+}
+
+/// ファイル中の `do_it!` / `あとは任せた!` の呼び出しを探す
+pub fn find_invocations(content: &str) -> Vec<(String, TokenStream)> {
+    struct MacroVisitor(Vec<(String, TokenStream)>);
+
+    impl<'ast> Visit<'ast> for MacroVisitor {
+        fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+            if let Some(segment) = mac.path.segments.last() {
+                let name = segment.ident.to_string();
+                if is_known_macro(&name) {
+                    self.0.push((name, mac.tokens.clone()));
+                }
+            }
+            syn::visit::visit_macro(self, mac);
+        }
+    }
+
+    let Ok(file) = syn::parse_file(content) else {
+        return Vec::new();
+    };
+    let mut visitor = MacroVisitor(Vec::new());
+    visitor.visit_file(&file);
+    visitor.0
+}
+
+/// 呼び出しがアイテムの位置 (ファイルや `mod` の直下) にあるか
+///
+/// `include!` は文や式の位置では使えないので、同じ呼び出しが他の位置にもあれば `false` にする
+pub fn is_item_invocation(content: &str, macro_name: &str, tokens: &TokenStream) -> bool {
+    struct MacroVisitor<'a> {
+        macro_name: &'a str,
+        expected: Vec<String>,
+        items: usize,
+        all: usize,
+    }
+
+    impl MacroVisitor<'_> {
+        fn matches(&self, mac: &syn::Macro) -> bool {
+            mac.path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == self.macro_name)
+                && token_strings(mac.tokens.clone()) == self.expected
+        }
+    }
+
+    impl<'ast> Visit<'ast> for MacroVisitor<'_> {
+        fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+            if self.matches(&item.mac) {
+                self.items += 1;
+            }
+            syn::visit::visit_item_macro(self, item);
+        }
+
+        fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+            if self.matches(mac) {
+                self.all += 1;
+            }
+            syn::visit::visit_macro(self, mac);
+        }
+    }
+
+    let Ok(file) = syn::parse_file(content) else {
+        return false;
+    };
+    let mut visitor = MacroVisitor {
+        macro_name,
+        expected: token_strings(tokens.clone()),
+        items: 0,
+        all: 0,
+    };
+    visitor.visit_file(&file);
+    visitor.items > 0 && visitor.items == visitor.all
+}
+
+/// target/ や隠しディレクトリを除いて .rs ファイルを列挙する
+pub fn find_rust_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    let current_dir = env::current_dir()?;
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with('.') || name == "target" || name == "gpt_responses" {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                // rustcに渡されるパスと揃えるため、なるべく相対パスにする
+                let path = match path.strip_prefix(&current_dir) {
+                    Ok(relative) => relative.to_path_buf(),
+                    Err(_) => path,
+                };
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_invocations() {
+        let content = r#"
+use hey_chat_gpt::do_it;
+
+hey_chat_gpt::do_it!(pub, model = "gpt-4o");
+
+fn main() {
+    println!("{}", fib(10));
+}
+"#;
+        let invocations = find_invocations(content);
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].0, "do_it");
+
+        let tokens = "pub ,model=\"gpt-4o\"".parse::<TokenStream>().unwrap();
+        assert_eq!(
+            token_strings(invocations[0].1.clone()),
+            token_strings(tokens)
+        );
+    }
+
+    #[test]
+    fn test_is_item_invocation() {
+        let content = r#"
+do_it!("helpers");
+
+mod inner {
+    hey_chat_gpt::do_it!("inner");
+}
+
+fn main() {
+    do_it!("statement");
+    let x = do_it!("expression");
+    do_it!("helpers");
+}
+"#;
+        let is_item = |tokens: &str| is_item_invocation(content, "do_it", &tokens.parse().unwrap());
+        assert!(is_item("\"inner\""));
+        assert!(!is_item("\"statement\""));
+        assert!(!is_item("\"expression\""));
+        // 文の位置にも同じ呼び出しがあればどちらか分からないのでその場に展開する
+        assert!(!is_item("\"helpers\""));
+        assert!(!is_item("\"missing\""));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::config::Settings;
+use proc_macro2::{Span, TokenStream};
+use syn::Ident;
+use syn::LitBool;
+use syn::LitInt;
+use syn::LitStr;
+use syn::Token;
+use syn::Visibility;
+use syn::{parse::Parse, parse::ParseStream};
+
+pub struct MacroInput {
+    pub vis: Visibility,
+    pub prompt: Option<LitStr>,
+    pub refine: Option<String>,
+    /// `signatures { ... }` の中身 (`context = "none"` で使う)
+    pub signatures: Vec<TokenStream>,
+    /// `examples { ... }` の中身 (`context = "none"` で使う)
+    pub examples: Vec<TokenStream>,
+    /// 設定の最上位の層
+    pub settings: Settings,
+    /// 呼び出しのトークン列 (呼び出し元のファイルを探すのに使う)
+    pub tokens: TokenStream,
+}
+
+impl Parse for MacroInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut settings = Settings::default();
+        let mut prompt = None;
+        let mut refine = None;
+        let mut signatures = Vec::new();
+        let mut examples = Vec::new();
+        let tokens = input.fork().parse::<TokenStream>()?;
+
+        let vis = input.parse::<Visibility>()?;
+
+        fn parse_puncts(input: ParseStream) -> syn::Result<()> {
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            } else if input.peek(Token![;]) {
+                input.parse::<Token![;]>()?;
+            }
+            Ok(())
+        }
+
+        while !input.is_empty() {
+            let lookahead = input.lookahead1();
+            if lookahead.peek(Ident) {
+                let ident = input.parse::<Ident>()?;
+                if input.peek(syn::token::Brace) && (ident == "signatures" || ident == "examples") {
+                    let content;
+                    syn::braced!(content in input);
+                    let block = content.parse::<TokenStream>()?;
+                    match ident == "signatures" {
+                        true => signatures.push(block),
+                        false => examples.push(block),
+                    }
+                    parse_puncts(input)?;
+                    continue;
+                }
+                input.parse::<syn::Token![=]>()?;
+                match ident {
+                    i if i == "model" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.model = Some(value.value());
+                    }
+                    i if i == "max_completion_tokens" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.max_completion_tokens = Some(value.base10_parse()?);
+                    }
+                    i if i == "max_retries" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.max_retries = Some(value.base10_parse()?);
+                    }
+                    i if i == "timeout_secs" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.timeout_secs = Some(value.base10_parse()?);
+                    }
+                    i if i == "headers" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.headers = Some(value.value());
+                    }
+                    i if i == "seed" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.seed = Some(value.base10_parse()?);
+                    }
+                    i if i == "code_tags" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.code_tags = Some(
+                            value
+                                .value()
+                                .split(',')
+                                .map(|tag| tag.trim().to_string())
+                                .filter(|tag| !tag.is_empty())
+                                .collect(),
+                        );
+                    }
+                    i if i == "untagged_fences" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.untagged_fences =
+                            Some(value.value().parse().into_syn(value.span())?);
+                    }
+                    i if i == "offline" => {
+                        let value = input.parse::<LitBool>()?;
+                        settings.offline = Some(value.value());
+                    }
+                    i if i == "pin" => {
+                        let value = input.parse::<LitBool>()?;
+                        settings.pin = Some(value.value());
+                    }
+                    i if i == "lang" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.lang = Some(value.value());
+                    }
+                    i if i == "context" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.context = Some(value.value().parse().into_syn(value.span())?);
+                    }
+                    i if i == "context_max_tokens" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.context_max_tokens = Some(value.base10_parse()?);
+                    }
+                    i if i == "max_prompt_tokens" => {
+                        let value = input.parse::<LitInt>()?;
+                        settings.max_prompt_tokens = Some(value.base10_parse()?);
+                    }
+                    i if i == "redaction" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.redaction = Some(value.value().parse().into_syn(value.span())?);
+                    }
+                    i if i == "system_prompt" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.system_prompt = Some(value.value());
+                    }
+                    i if i == "system_prompt_file" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.system_prompt_file = Some(value.value().into());
+                    }
+                    i if i == "system_prompt_mode" => {
+                        let value = input.parse::<LitStr>()?;
+                        settings.system_prompt_mode =
+                            Some(value.value().parse().into_syn(value.span())?);
+                    }
+                    i if i == "refine" => {
+                        let value = input.parse::<LitStr>()?;
+                        refine = Some(value.value());
+                    }
+                    _ => return Err(lookahead.error()),
+                }
+            } else if lookahead.peek(LitStr) {
+                prompt = Some(input.parse()?);
+            } else {
+                return Err(lookahead.error());
+            }
+            parse_puncts(input)?;
+        }
+
+        Ok(Self {
+            vis,
+            prompt,
+            refine,
+            signatures,
+            examples,
+            settings,
+            tokens,
+        })
+    }
+}
+
+pub trait IntoSynRes<T> {
+    fn into_syn(self, span: Span) -> syn::Result<T>;
+}
+
+impl<T, E> IntoSynRes<T> for Result<T, E>
+where
+    E: std::fmt::Display + std::fmt::Debug,
+{
+    fn into_syn(self, span: Span) -> syn::Result<T> {
+        match self {
+            Ok(ok) => Ok(ok),
+            Err(err) => Err(syn::Error::new(span, err)),
+        }
+    }
+}
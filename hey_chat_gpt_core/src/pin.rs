@@ -0,0 +1,205 @@
+//! `pin = true` の呼び出しで採用した出力を `hey_gpt.lock` に記録する
+//!
+//! 呼び出しは ファイル + マクロ名 + マクロの入力 で識別するので、同じファイルの他の場所を編集しても再生成されない。
+//! マクロの引数を変えるか `cargo hey-gpt unpin` で外すと、次のビルドで生成し直す
+
+use anyhow::{bail, Context};
+use proc_macro2::TokenStream;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use super::cache::get_state_dir;
+use super::config::{manifest_dir, relative_to_manifest_dir};
+use super::locate::{find_invocations, token_strings};
+use super::util::{lock_file, write_atomic};
+
+pub const LOCK_FILE_NAME: &str = "hey_gpt.lock";
+
+pub fn lock_path() -> PathBuf {
+    manifest_dir().join(LOCK_FILE_NAME)
+}
+
+/// ロックファイルを読んで書き直す間、同じクレートを展開している他のプロセスを待たせる
+pub fn guard() -> anyhow::Result<File> {
+    let path = get_state_dir().join("locks").join("pin.lock");
+    lock_file(&path).with_context(|| format!("failed to lock {}", path.display()))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lock {
+    #[serde(default, rename = "pin")]
+    pub pins: Vec<Pin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    /// マニフェストディレクトリからの相対パス
+    pub file: String,
+    #[serde(rename = "macro")]
+    pub macro_name: String,
+    /// マクロの入力のハッシュ (空白の違いは無視する)
+    pub input: String,
+    pub output_sha256: String,
+    /// `gpt_responses` を消しても再現できるよう、出力そのものを持つ
+    pub output: String,
+}
+
+impl Lock {
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(&lock_path())
+    }
+
+    pub fn load_from(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.save_to(&lock_path())
+    }
+
+    /// 途中で止まっても壊れたロックファイルが残らないよう、一時ファイルに書いてから置き換える
+    pub fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        let content = format!(
+            "# Pinned outputs of hey_chat_gpt. Remove entries with `cargo hey-gpt unpin`.\n\n{}",
+            toml::to_string(self)?
+        );
+        write_atomic(path, &content).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// 固定された出力を返す。書き換えられていればエラーにする
+    pub fn find(
+        &self,
+        source_file_path: &Path,
+        macro_name: &str,
+        tokens: &TokenStream,
+    ) -> anyhow::Result<Option<&str>> {
+        let file = lock_file_key(source_file_path);
+        let input = input_hash(tokens);
+        let Some(pin) = self
+            .pins
+            .iter()
+            .find(|pin| pin.file == file && pin.macro_name == macro_name && pin.input == input)
+        else {
+            return Ok(None);
+        };
+        if output_sha256(&pin.output) != pin.output_sha256 {
+            bail!(
+                "the output pinned for `{}!` in {} does not match its `output_sha256` in {}; \
+                 restore it or run `cargo hey-gpt unpin {}`",
+                macro_name,
+                file,
+                LOCK_FILE_NAME,
+                file
+            );
+        }
+        Ok(Some(&pin.output))
+    }
+
+    /// 出力を固定する。引数が変わってファイルにもう存在しない呼び出しの記録は取り除く
+    pub fn pin(
+        &mut self,
+        source_file_path: &Path,
+        macro_name: &str,
+        tokens: &TokenStream,
+        output: &str,
+    ) {
+        let file = lock_file_key(source_file_path);
+        let input = input_hash(tokens);
+        let live_inputs = fs::read_to_string(source_file_path)
+            .map(|content| {
+                find_invocations(&content)
+                    .into_iter()
+                    .filter(|(name, _)| name == macro_name)
+                    .map(|(_, tokens)| input_hash(&tokens))
+                    .collect::<Vec<_>>()
+            })
+            .ok();
+        self.pins.retain(|pin| {
+            pin.file != file
+                || pin.macro_name != macro_name
+                || (pin.input != input
+                    && live_inputs
+                        .as_ref()
+                        .is_none_or(|inputs| inputs.contains(&pin.input)))
+        });
+        self.pins.push(Pin {
+            file,
+            macro_name: macro_name.to_string(),
+            input,
+            output_sha256: output_sha256(output),
+            output: output.to_string(),
+        });
+        self.pins
+            .sort_by(|a, b| (&a.file, &a.input).cmp(&(&b.file, &b.input)));
+    }
+}
+
+/// OSによらず同じロックファイルになるよう `/` 区切りにする
+pub fn lock_file_key(source_file_path: &Path) -> String {
+    relative_to_manifest_dir(source_file_path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn input_hash(tokens: &TokenStream) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(token_strings(tokens.clone()).join(" "))
+    )
+}
+
+fn output_sha256(output: &str) -> String {
+    format!("{:x}", Sha256::digest(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::quote;
+
+    #[test]
+    fn test_lock_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("hey_gpt_pin_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lock_path = dir.join(LOCK_FILE_NAME);
+        let source = Path::new("src/pinned.rs");
+
+        let tokens = quote! { "fib", pin = true };
+        let mut lock = Lock::default();
+        lock.pin(source, "do_it", &tokens, "pub fn fib() {}\n");
+        lock.save_to(&lock_path).unwrap();
+
+        let lock = Lock::load_from(&lock_path).unwrap();
+        assert_eq!(lock.pins.len(), 1);
+        // 空白の違いは同じ呼び出しとみなす
+        let reformatted = "\"fib\" ,\n pin = true".parse().unwrap();
+        assert_eq!(
+            lock.find(source, "do_it", &reformatted).unwrap(),
+            Some("pub fn fib() {}\n")
+        );
+        assert_eq!(
+            lock.find(source, "do_it", &quote! { "fib2", pin = true })
+                .unwrap(),
+            None
+        );
+        assert_eq!(lock.find(source, "あとは任せた", &tokens).unwrap(), None);
+
+        let tampered = fs::read_to_string(&lock_path)
+            .unwrap()
+            .replace("fib()", "fib2()");
+        fs::write(&lock_path, tampered).unwrap();
+        let lock = Lock::load_from(&lock_path).unwrap();
+        assert!(lock.find(source, "do_it", &tokens).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
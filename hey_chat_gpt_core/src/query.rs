@@ -0,0 +1,523 @@
+use anyhow::Context;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder};
+use reqwest::{Certificate, Identity};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+use super::audit::{Audit, Outcome};
+use super::config::Settings;
+use super::retry::{is_retryable_error, is_retryable_status, RetryPolicy};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RequestBody {
+    model: String,
+    messages: Vec<Message>,
+    seed: u64,
+    max_completion_tokens: Option<u64>,
+}
+
+impl RequestBody {
+    pub fn new(
+        model: String,
+        messages: &[Message],
+        seed: u64,
+        max_completion_tokens: Option<u64>,
+    ) -> Self {
+        Self {
+            model,
+            messages: Vec::from(messages),
+            seed: seed % 9223372036854775807,
+            max_completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Choice {
+    index: u64,
+    message: Message,
+    finish_reason: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ResponseBody {
+    id: String,
+    object: String,
+    created: u64,
+    choices: Vec<Choice>,
+    usage: Usage,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    pub endpoint: String,
+    pub retry: RetryPolicy,
+    /// 1回のリクエスト全体のタイムアウト
+    pub timeout: Duration,
+    /// `OpenAI-Organization` やゲートウェイの認証トークンなど
+    pub extra_headers: Vec<(String, String)>,
+}
+
+impl QueryOptions {
+    pub fn new(settings: &Settings) -> anyhow::Result<Self> {
+        // 後に書かれたヘッダが同名の先のヘッダを上書きする
+        let mut extra_headers: Vec<(String, String)> = Vec::new();
+        if let Some(headers) = &settings.headers {
+            for (name, value) in parse_headers(headers).context("invalid `headers`")? {
+                extra_headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+                extra_headers.push((name, value));
+            }
+        }
+
+        Ok(Self {
+            endpoint: settings.endpoint().to_string(),
+            retry: RetryPolicy::new(settings.max_retries()),
+            timeout: settings.timeout(),
+            extra_headers,
+        })
+    }
+
+    fn client(&self) -> anyhow::Result<Client> {
+        let builder = client_builder()?
+            .connect_timeout(CONNECT_TIMEOUT.min(self.timeout))
+            .timeout(self.timeout);
+
+        Ok(builder.build()?)
+    }
+
+    /// タイムアウトも再送するので、試した回数と全体でかかった時間を伝える
+    fn timeout_error(&self, model: &str, attempts: u32, elapsed: Duration) -> anyhow::Error {
+        anyhow::anyhow!(
+            "request to {} (model: {}) timed out: {} attempt(s) of up to {}s each, \
+             {:.1}s in total. Increase `timeout_secs` or HEY_GPT_TIMEOUT, \
+             or build from the cache: run `cargo hey-gpt prefetch` beforehand \
+             and set HEY_GPT_OFFLINE=1 to never send requests while compiling.",
+            self.endpoint,
+            model,
+            attempts,
+            self.timeout.as_secs_f64(),
+            elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// `Name: value` を `;` または改行で区切って並べたものを読む
+fn parse_headers(headers: &str) -> anyhow::Result<Vec<(String, String)>> {
+    headers
+        .split([';', '\n'])
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .with_context(|| format!("expected `Name: value`, found `{}`", header))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+fn common_header(client: &Client, options: &QueryOptions, api_key: &str) -> RequestBuilder {
+    let api_key_field = format!("Bearer {}", api_key);
+
+    let mut builder = client
+        .post(&options.endpoint)
+        .header("Content-Type", "application/json")
+        .header("Authorization", api_key_field.as_str());
+    for (name, value) in &options.extra_headers {
+        builder = builder.header(name, value);
+    }
+    builder
+}
+
+/// 受け取った応答
+#[derive(Debug)]
+pub struct Completion {
+    pub message: Message,
+    pub usage: Usage,
+    pub response_id: String,
+    /// 実際に送ったJSON (監査ログにはこのハッシュを記録する)
+    pub request_json: String,
+}
+
+/// 送って応答を受け取る
+///
+/// 応答を受け取れなかった場合はここで監査ログに記録する。受け取れた場合は、記録に失敗しても
+/// 払った応答を失わないよう、呼び出し側でキャッシュしてから記録する
+pub fn query(
+    api_key: &str,
+    request_body: &RequestBody,
+    options: &QueryOptions,
+    audit: &Audit,
+) -> anyhow::Result<Completion> {
+    let client = options.client()?;
+    // 監査ログのハッシュが実際に送ったものと一致するよう、一度だけシリアライズする
+    let request_json = serde_json::to_string(&request_body)?;
+
+    let result = send(
+        &client,
+        options,
+        api_key,
+        &request_json,
+        &request_body.model,
+    )
+    .and_then(|body| {
+        // 読めない応答はキャッシュに書かず (コードとして展開されてしまう)、本文ごとエラーにする
+        serde_json::from_str::<ResponseBody>(&body)
+            .with_context(|| format!("unexpected response from {}: {}", options.endpoint, body))
+    });
+    let mut response_body = match result {
+        Ok(response_body) => response_body,
+        Err(err) => {
+            audit.record_request(
+                Outcome::Failed,
+                &request_body.model,
+                &options.endpoint,
+                &request_json,
+                None,
+            )?;
+            return Err(err);
+        }
+    };
+
+    Ok(Completion {
+        message: response_body.choices.remove(0).message,
+        usage: response_body.usage,
+        response_id: response_body.id,
+        request_json,
+    })
+}
+
+/// 証明書の設定をしたクライアント
+///
+/// プロキシは `HTTPS_PROXY` / `NO_PROXY` などの環境変数からreqwestが設定する
+pub fn client_builder() -> anyhow::Result<ClientBuilder> {
+    let mut builder = Client::builder();
+
+    // TLSを傍受するプロキシ用のルート証明書
+    if let Ok(path) = env::var("HEY_GPT_CA_BUNDLE") {
+        let pem = fs::read(&path).with_context(|| format!("failed to read {}", path))?;
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("invalid certificate in {}", path))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    // クライアント証明書
+    match (
+        env::var("HEY_GPT_CLIENT_CERT"),
+        env::var("HEY_GPT_CLIENT_KEY"),
+    ) {
+        (Ok(cert_path), Ok(key_path)) => {
+            let cert =
+                fs::read(&cert_path).with_context(|| format!("failed to read {}", cert_path))?;
+            let key =
+                fs::read(&key_path).with_context(|| format!("failed to read {}", key_path))?;
+            let identity = Identity::from_pkcs8_pem(&cert, &key)
+                .context("invalid client certificate or key")?;
+            builder = builder.identity(identity);
+        }
+        (Err(_), Err(_)) => {}
+        _ => anyhow::bail!("HEY_GPT_CLIENT_CERT and HEY_GPT_CLIENT_KEY must be set together"),
+    }
+
+    Ok(builder)
+}
+
+/// 送信して成功した応答の本文を返す
+fn send(
+    client: &Client,
+    options: &QueryOptions,
+    api_key: &str,
+    request_json: &str,
+    model: &str,
+) -> anyhow::Result<String> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let can_retry = attempt < options.retry.max_retries;
+        let result = common_header(client, options, api_key)
+            .body(request_json.to_string())
+            .send();
+
+        // 一時的な失敗(レート制限、サーバーエラー、接続エラー)なら待ってから再送する
+        let headers = match result {
+            Ok(response) if is_retryable_status(response.status()) => {
+                let status = response.status();
+                let headers = response.headers().clone();
+                if !can_retry {
+                    let body = response.text().unwrap_or_default();
+                    anyhow::bail!(
+                        "{} after {} retries: {}",
+                        status,
+                        options.retry.max_retries,
+                        body
+                    );
+                }
+                Some(headers)
+            }
+            // 認証エラーなど再送しても変わらない失敗は、本文を応答として扱わずにエラーにする
+            Ok(response) if !response.status().is_success() => {
+                let status = response.status();
+                let body = response.text().unwrap_or_default();
+                anyhow::bail!("{} from {}: {}", status, options.endpoint, body);
+            }
+            Ok(response) => match response.text() {
+                Ok(body) => return Ok(body),
+                Err(err) if err.is_timeout() => {
+                    return Err(options.timeout_error(model, attempt + 1, started.elapsed()))
+                }
+                Err(err) => return Err(err.into()),
+            },
+            Err(err) if can_retry && is_retryable_error(&err) => None,
+            Err(err) if err.is_timeout() => {
+                return Err(options.timeout_error(model, attempt + 1, started.elapsed()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        thread::sleep(options.retry.delay(attempt, headers.as_ref()));
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEntry;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    const SUCCESS_BODY: &str = r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,"choices":[{"index":0,"message":{"role":"assistant","content":"fn answer() -> u32 { 42 }"},"finish_reason":"stop"}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#;
+
+    fn http_response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+        response
+    }
+
+    /// 台本通りのレスポンスを順に返すモックサーバー。受け付けたリクエスト数を返す
+    fn mock_server(responses: Vec<String>) -> (String, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!(
+            "http://{}/v1/chat/completions",
+            listener.local_addr().unwrap()
+        );
+        let handle = thread::spawn(move || {
+            let mut count = 0;
+            for response in responses {
+                let Ok((stream, _)) = listener.accept() else {
+                    break;
+                };
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                count += 1;
+            }
+            count
+        });
+        (endpoint, handle)
+    }
+
+    fn test_audit(name: &str) -> (Audit, std::path::PathBuf) {
+        let path = env::temp_dir().join(format!(
+            "hey_chat_gpt_query_test_{}_{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let audit = Audit::with_path(path.clone(), std::path::Path::new("src/lib.rs"), "", false);
+        (audit, path)
+    }
+
+    fn run_query(endpoint: String, max_retries: u32) -> anyhow::Result<Completion> {
+        let options = QueryOptions {
+            endpoint,
+            retry: RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_millis(1),
+            },
+            timeout: Duration::from_secs(5),
+            extra_headers: Vec::new(),
+        };
+        let request_body = RequestBody::new(
+            "gpt-4o".to_string(),
+            &[Message {
+                role: Role::User,
+                content: "hello".to_string(),
+            }],
+            0,
+            None,
+        );
+        query(
+            "sk-test",
+            &request_body,
+            &options,
+            &test_audit(&max_retries.to_string()).0,
+        )
+    }
+
+    #[test]
+    fn test_retry_on_rate_limit_and_server_error() {
+        let (endpoint, server) = mock_server(vec![
+            http_response("429 Too Many Requests", &["Retry-After: 0"], "{}"),
+            http_response(
+                "503 Service Unavailable",
+                &["x-ratelimit-reset-requests: 1ms"],
+                "",
+            ),
+            http_response("200 OK", &["Content-Type: application/json"], SUCCESS_BODY),
+        ]);
+
+        let completion = run_query(endpoint, 3).unwrap();
+        assert_eq!(completion.message.content, "fn answer() -> u32 { 42 }");
+        assert_eq!(completion.usage.total_tokens, 2);
+        assert_eq!(completion.response_id, "chatcmpl-1");
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_give_up_after_max_retries() {
+        let (endpoint, server) = mock_server(vec![
+            http_response("500 Internal Server Error", &[], "oops"),
+            http_response("502 Bad Gateway", &[], "oops"),
+            http_response("504 Gateway Timeout", &[], "oops"),
+        ]);
+
+        let err = run_query(endpoint, 2).unwrap_err();
+        assert!(err.to_string().contains("504"), "{}", err);
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_no_retry_on_client_error() {
+        let (endpoint, server) = mock_server(vec![http_response(
+            "400 Bad Request",
+            &["Content-Type: application/json"],
+            r#"{"error":{"message":"bad"}}"#,
+        )]);
+
+        let err = run_query(endpoint, 1).unwrap_err().to_string();
+        assert!(err.contains("400 Bad Request"), "{}", err);
+        assert!(err.contains(r#"{"error":{"message":"bad"}}"#), "{}", err);
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unexpected_response_is_an_error() {
+        let (endpoint, server) = mock_server(vec![http_response(
+            "200 OK",
+            &["Content-Type: application/json"],
+            "not a completion",
+        )]);
+
+        let err = format!("{:#}", run_query(endpoint, 0).unwrap_err());
+        assert!(err.contains("not a completion"), "{}", err);
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_timeout_error_names_model_and_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!(
+            "http://{}/v1/chat/completions",
+            listener.local_addr().unwrap()
+        );
+        // 接続は受け付けるが返事をしないサーバー
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(2));
+        });
+
+        let options = QueryOptions {
+            endpoint: endpoint.clone(),
+            retry: RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(1),
+            },
+            timeout: Duration::from_millis(200),
+            extra_headers: Vec::new(),
+        };
+        let (audit, audit_path) = test_audit("timeout");
+        let err = query(
+            "sk-test",
+            &RequestBody::new("gpt-4o".to_string(), &[], 0, None),
+            &options,
+            &audit,
+        )
+        .unwrap_err()
+        .to_string();
+
+        // タイムアウトも再送するので、2回試したことになる
+        assert!(err.contains("2 attempt(s) of up to 0.2s each"), "{}", err);
+        assert!(err.contains("gpt-4o"), "{}", err);
+        assert!(err.contains(&endpoint), "{}", err);
+        server.join().unwrap();
+
+        // 応答がなくても送ったことは記録する
+        let entries = fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].outcome, Outcome::Failed);
+        assert!(entries[0].request_sha256.is_some());
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers(
+            "OpenAI-Organization: org-123; OpenAI-Project: proj_1\nX-Gateway-Token: a:b;",
+        )
+        .unwrap();
+        assert_eq!(
+            headers,
+            vec![
+                ("OpenAI-Organization".to_string(), "org-123".to_string()),
+                ("OpenAI-Project".to_string(), "proj_1".to_string()),
+                ("X-Gateway-Token".to_string(), "a:b".to_string()),
+            ]
+        );
+        assert!(parse_headers("no-colon").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::bail;
+use std::path::{Path, PathBuf};
+
+use super::audit::{Audit, Outcome};
+use super::cache::{
+    cache_key, cache_result, cache_transcript, caller_id, hash_content, load_cache,
+    load_latest_transcript, lock_key, mark_latest,
+};
+use super::config::{Config, ContextMode, Settings};
+use super::context::crate_context;
+use super::ledger::{estimated_completion_tokens, price_for, record, reserve_budget};
+use super::query::{query, Message, QueryOptions, RequestBody, Role};
+use super::redact::{Redactions, Redactor};
+use super::tokenizer::{check_tokenizer, count_message_tokens, count_tokens, prompt_token_limit};
+use super::MacroInput;
+
+/// 前回の会話履歴があればその続きに、なければ今回組み立てたメッセージに `refine` の指示を足す
+fn refine_messages(
+    messages: Vec<Message>,
+    transcript: Option<Vec<Message>>,
+    refine: &str,
+) -> Vec<Message> {
+    let mut messages = match transcript {
+        Some(mut transcript) => {
+            // 同じrefineでの再生成ならその1往復をやり直す
+            if let [.., Message {
+                role: Role::User,
+                content: last_refine,
+            }, Message {
+                role: Role::Assistant,
+                ..
+            }] = transcript.as_slice()
+            {
+                if last_refine == refine {
+                    transcript.truncate(transcript.len() - 2);
+                }
+            }
+            transcript
+        }
+        None => messages,
+    };
+    messages.push(Message {
+        role: Role::User,
+        content: refine.to_string(),
+    });
+    messages
+}
+
+/// この呼び出しのキャッシュのキー。 `context = "crate"` ならクレートのコンテキストも組み立てて含める
+pub fn request_key(
+    input: &MacroInput,
+    settings: &Settings,
+    system_message: &str,
+    source_file_path: &Path,
+    content: &str,
+) -> String {
+    let context = (settings.context() == ContextMode::Crate)
+        .then(|| crate_context(source_file_path, settings.context_max_tokens()));
+    cache_key(
+        content,
+        settings,
+        system_message,
+        input.refine.as_deref(),
+        context.as_deref(),
+    )
+}
+
+/// キャッシュヒット時に、送った時と同じ順 (ファイル、クレートのコンテキスト) で伏せ直して元に戻すための対応を作る
+pub fn cached_redactions(
+    settings: &Settings,
+    source_file_path: &Path,
+    content: &str,
+) -> anyhow::Result<Redactions> {
+    let mut redactor = Redactor::for_restore(settings)?;
+    redactor.redact(content)?;
+    if settings.context() == ContextMode::Crate {
+        redactor.redact(&crate_context(
+            source_file_path,
+            settings.context_max_tokens(),
+        ))?;
+    }
+    Ok(redactor.into_redactions())
+}
+
+/// 1回のAPI呼び出しに必要な情報
+///
+/// マクロ展開時とプリフェッチ時の両方で同じリクエストを組み立てるために使う
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// 内容と設定から作ったキャッシュのキー
+    pub key: String,
+    pub source_file_path: PathBuf,
+    pub messages: Vec<Message>,
+    pub seed: u64,
+    /// 全ての層を重ねた設定
+    pub settings: Settings,
+    /// 送信前に伏せた値 (返答を展開する時に戻す)
+    pub redactions: Redactions,
+    /// 同じファイルの他の呼び出しと区別する値 (最新の生成の記録とキャッシュの整理に使う)
+    pub caller: String,
+}
+
+impl Request {
+    pub fn new(
+        macro_name: &str,
+        input: &MacroInput,
+        settings: Settings,
+        system_message: &str,
+        source_file_path: PathBuf,
+        content: String,
+    ) -> anyhow::Result<Self> {
+        check_tokenizer()?;
+        let key = request_key(
+            input,
+            &settings,
+            system_message,
+            &source_file_path,
+            &content,
+        );
+        let caller = caller_id(macro_name, &input.tokens);
+        let system_message = Message {
+            role: Role::User, // 本当はSystemとしたいがo1-previewで撤廃されたらしい
+            content: system_message.to_string(),
+        };
+        // マクロ展開時のキャッシュヒットと同じ番号になるよう、ファイルを最初に伏せる
+        let mut redactor = Redactor::new(&settings)?;
+        let user_message = Message {
+            role: Role::User,
+            content: redactor.redact(&content)?,
+        };
+        let mut messages = vec![system_message];
+        // 後で削れるように、新しく組み立てたコンテキストの位置と予算を覚えておく
+        let mut context = None;
+        if settings.context() == ContextMode::Crate {
+            let budget = settings.context_max_tokens();
+            messages.push(Message {
+                role: Role::User,
+                content: redactor.redact(&crate_context(&source_file_path, budget))?,
+            });
+            context = Some((messages.len() - 1, budget));
+        }
+        messages.push(user_message);
+
+        // refineが指定されている場合は前回の会話の続きとして依頼する
+        if let Some(refine) = &input.refine {
+            let transcript = load_latest_transcript(&source_file_path, &caller);
+            if transcript.is_some() {
+                context = None;
+            }
+            messages = refine_messages(messages, transcript, refine);
+        }
+
+        let seed = match settings.seed {
+            Some(seed) => seed,
+            None => hash_content(&content),
+        };
+
+        if let Some(limit) = prompt_token_limit(&settings) {
+            let mut tokens = count_message_tokens(&messages);
+            // 溢れた分だけコンテキストを削り、それでも足りなければコンテキストごと諦める
+            if let (true, Some((context_index, budget))) = (tokens > limit, context) {
+                let budget = budget.saturating_sub(tokens - limit);
+                let context = redactor.redact(&crate_context(&source_file_path, budget))?;
+                if count_tokens(&context) <= budget {
+                    messages[context_index].content = context;
+                } else {
+                    messages.remove(context_index);
+                }
+                tokens = count_message_tokens(&messages);
+            }
+            if tokens > limit {
+                bail!(
+                    "the prompt is {} tokens, which exceeds the limit of {} tokens for `{}` (split the file into smaller modules)",
+                    tokens,
+                    limit,
+                    settings.model()
+                );
+            }
+        }
+
+        Ok(Self {
+            key,
+            source_file_path,
+            messages,
+            seed,
+            caller,
+            settings,
+            redactions: redactor.into_redactions(),
+        })
+    }
+
+    /// APIに問い合わせ、結果と会話履歴をキャッシュする
+    ///
+    /// キャッシュと同じく伏せたままの応答を返す。展開する時に `redactions` で元に戻す
+    pub fn send(self, api_key: &str) -> anyhow::Result<String> {
+        let Self {
+            key,
+            source_file_path,
+            mut messages,
+            seed,
+            settings,
+            caller,
+            ..
+        } = self;
+
+        let config = Config::load()?;
+        // 同じ内容を展開している他のrustcやプリフェッチがあれば、二重に払わずその応答を使う
+        let _lock = lock_key(&key)?;
+        if let Some(cache) = load_cache(&key)? {
+            mark_latest(&key, &source_file_path, &caller)?;
+            Audit::new(&source_file_path, &key, &config).record_hit(&settings)?;
+            return Ok(cache);
+        }
+
+        let options = QueryOptions::new(&settings)?;
+        // 返答の長さは分からないので、上限が指定されていなければモデルごとの目安で見積もる
+        let estimated_cost = price_for(settings.model(), &config.pricing).map_or(0.0, |price| {
+            price.cost(
+                count_message_tokens(&messages),
+                settings
+                    .max_completion_tokens
+                    .unwrap_or_else(|| estimated_completion_tokens(settings.model())),
+            )
+        });
+        // 実際の費用を台帳に記録するまで、見積もりを予約しておく
+        let _reservation = reserve_budget(&config, &key, estimated_cost)?;
+
+        let request_body = RequestBody::new(
+            settings.model().to_string(),
+            &messages,
+            seed,
+            settings.max_completion_tokens,
+        );
+        let audit = Audit::new(&source_file_path, &key, &config);
+        let completion = query(api_key, &request_body, &options, &audit)?;
+        let res_code = completion.message.content.clone();
+        messages.push(completion.message);
+
+        cache_result(
+            &key,
+            &res_code,
+            &source_file_path,
+            &caller,
+            settings.model(),
+        )?;
+        cache_transcript(&key, &source_file_path, &caller, &messages)?;
+        record(
+            &source_file_path,
+            settings.model(),
+            &completion.usage,
+            &config,
+        )?;
+        // 記録に失敗しても応答はもうキャッシュにあるので、次のビルドで払い直すことはない
+        audit.record_request(
+            Outcome::Miss,
+            settings.model(),
+            &options.endpoint,
+            &completion.request_json,
+            Some(completion.response_id),
+        )?;
+
+        Ok(res_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_refine_messages() {
+        let messages = vec![message(Role::User, "system"), message(Role::User, "file")];
+        let transcript = vec![
+            message(Role::User, "system"),
+            message(Role::User, "old file"),
+            message(Role::Assistant, "fn f() {}"),
+        ];
+
+        // 前回の会話の続きに指示を足す
+        let refined = refine_messages(messages.clone(), Some(transcript.clone()), "use u64");
+        assert_eq!(refined.len(), 4);
+        assert_eq!(refined[..3], transcript[..]);
+        assert_eq!(refined[3], message(Role::User, "use u64"));
+
+        // 同じ指示での再生成は前回の1往復をやり直す
+        let mut refined_once = refined.clone();
+        refined_once.push(message(Role::Assistant, "fn f() -> u64 { 0 }"));
+        assert_eq!(
+            refine_messages(messages.clone(), Some(refined_once.clone()), "use u64"),
+            refined
+        );
+        assert_eq!(
+            refine_messages(messages.clone(), Some(refined_once), "add docs").len(),
+            6
+        );
+
+        // 会話履歴がなければ今回組み立てたメッセージに足す
+        let refined = refine_messages(messages.clone(), None, "use u64");
+        assert_eq!(refined[..2], messages[..]);
+        assert_eq!(refined[2], message(Role::User, "use u64"));
+    }
+}
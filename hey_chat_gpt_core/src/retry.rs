@@ -0,0 +1,151 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// サーバーに指示された待ち時間でもこれ以上は待たない
+const MAX_SERVER_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+        }
+    }
+
+    /// `attempt` 回目(0始まり)の失敗の後に待つ時間
+    pub fn delay(&self, attempt: u32, headers: Option<&HeaderMap>) -> Duration {
+        if let Some(delay) = headers.and_then(server_delay) {
+            return delay.min(MAX_SERVER_DELAY);
+        }
+
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF);
+        // 同時に失敗したリクエストが一斉に再送しないよう半分をランダムにする
+        let half = backoff / 2;
+        half + half.mul_f64(random_unit())
+    }
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+pub fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request()
+}
+
+/// `Retry-After` と `x-ratelimit-reset-*` ヘッダから待ち時間を読み取る
+fn server_delay(headers: &HeaderMap) -> Option<Duration> {
+    let retry_after = headers
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64);
+
+    let reset = ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| headers.get(*name))
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(parse_reset_duration)
+        .max();
+
+    retry_after.into_iter().chain(reset).max()
+}
+
+/// `1s`, `6m0s`, `20ms`, `1h2m3.5s` のような形式を読む
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number = rest[..number_len].parse::<f64>().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total += number * scale;
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
+
+/// [0, 1) の乱数 (ジッター用なので質は問わない)
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_reset_duration() {
+        assert_eq!(parse_reset_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            parse_reset_duration("1h2m3.5s"),
+            Some(Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(parse_reset_duration(""), None);
+        assert_eq!(parse_reset_duration("soon"), None);
+    }
+
+    #[test]
+    fn test_server_delay_prefers_longest() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("3s"));
+        assert_eq!(server_delay(&headers), Some(Duration::from_secs(3)));
+
+        let policy = RetryPolicy::new(3);
+        assert_eq!(policy.delay(0, Some(&headers)), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_backoff_is_jittered_exponential() {
+        let policy = RetryPolicy::new(3);
+        for attempt in 0..10 {
+            let backoff = DEFAULT_INITIAL_BACKOFF
+                .saturating_mul(2u32.pow(attempt))
+                .min(MAX_BACKOFF);
+            let delay = policy.delay(attempt, None);
+            assert!(backoff / 2 <= delay && delay <= backoff);
+        }
+    }
+}
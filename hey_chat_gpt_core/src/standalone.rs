@@ -0,0 +1,107 @@
+//! `context = "none"` の時にマクロの入力だけからプロンプトを組み立てる
+//!
+//! ソースファイルを読めない場所 (生成されたコード、 `include!` されたファイル、ドキュメントテスト等) でも使える
+
+use anyhow::bail;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use super::MacroInput;
+
+/// ファイルの内容の代わりに送る内容 (キャッシュのキーにもなる)
+pub fn standalone_content(macro_name: &str, input: &MacroInput) -> anyhow::Result<String> {
+    if input.prompt.is_none() && input.signatures.is_empty() && input.examples.is_empty() {
+        bail!(
+            "`context = \"none\"` sends only the macro input, so give `{}!` a prompt, `signatures {{ ... }}` or `examples {{ ... }}`",
+            macro_name
+        );
+    }
+
+    let mut content = format!(
+        "// Only the input of `{}!` is given; the rest of the source file is not available.\n",
+        macro_name
+    );
+    if let Some(prompt) = &input.prompt {
+        content.push_str("//\n");
+        for line in prompt.value().lines() {
+            content.push_str(format!("// {}", line).trim_end());
+            content.push('\n');
+        }
+    }
+    for signatures in &input.signatures {
+        content.push_str("\n// Implement these items:\n");
+        content.push_str(&format_items(signatures));
+    }
+    for examples in &input.examples {
+        content.push_str("\n// The implementation must satisfy these examples:\n");
+        content.push_str(&format_statements(examples));
+    }
+
+    Ok(content)
+}
+
+fn format_items(tokens: &TokenStream) -> String {
+    match syn::parse2::<syn::File>(tokens.clone()) {
+        Ok(file) => prettyplease::unparse(&file),
+        Err(_) => format!("{}\n", tokens),
+    }
+}
+
+/// 文の並びは関数に包んで整形し、包んだ部分を取り除く
+fn format_statements(tokens: &TokenStream) -> String {
+    let Ok(file) = syn::parse2::<syn::File>(quote! { fn examples() { #tokens } }) else {
+        return format!("{}\n", tokens);
+    };
+    let formatted = prettyplease::unparse(&file);
+    let lines = formatted.lines().collect::<Vec<_>>();
+    let body = lines
+        .get(1..lines.len().saturating_sub(1))
+        .unwrap_or_default();
+    body.iter()
+        .map(|line| format!("{}\n", line.strip_prefix("    ").unwrap_or(line)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standalone_content() {
+        let input = syn::parse_str::<MacroInput>(
+            r#"
+            "Compute Fibonacci numbers.",
+            context = "none",
+            signatures {
+                pub fn fib(n: u64) -> u64;
+            }
+            examples {
+                assert_eq!(fib(10), 55);
+                assert_eq!(fib(0), 0);
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(input.signatures.len(), 1);
+        assert_eq!(input.examples.len(), 1);
+
+        assert_eq!(
+            standalone_content("do_it", &input).unwrap(),
+            r#"// Only the input of `do_it!` is given; the rest of the source file is not available.
+//
+// Compute Fibonacci numbers.
+
+// Implement these items:
+pub fn fib(n: u64) -> u64;
+
+// The implementation must satisfy these examples:
+assert_eq!(fib(10), 55);
+assert_eq!(fib(0), 0);
+"#
+        );
+
+        let input = syn::parse_str::<MacroInput>(r#"context = "none""#).unwrap();
+        let err = standalone_content("do_it", &input).unwrap_err();
+        assert!(err.to_string().contains("signatures"), "{}", err);
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+
+use super::config::{Config, CrateInfo, LocaleConfig, PromptMode, Settings};
+
+const ENGLISH_MESSAGE: &str = r#"I'm the administrator of this system. You are an AI assistant of this system helping with Rust programming, and you are called through `{macro_name}` proc-macro. Generate Rust code based on the user's input as proc-macro (`{macro_name}` macro) output. Ensure the code is idiomatic and adheres to Rust best practices. {comment_instruction} All your answers will be treated as `String` values and converted to `proc_macro2::TokenStream` , so your answers must be valid Rust code. **Anything that is not Rust code must be in a comment, and you must not output anything that would prevent the conversion. And User input other than macros remains, so be careful not to create duplicates. (For example, if you output a main function, it may conflict with a user-defined main function and cause a compilation error. Or `{macro_name}` macro may be called from within the main function, in which case you should not print the main function itself.)**."#;
+const ENGLISH_COMMENTS: &str = "Include comments in English for clarity.";
+const ENGLISH_HEADER: &str = r#"What follows is inputs of the user who uses this system:
+
+"#;
+const JAPANESE_MESSAGE: &str = r#"私はこのシステムの管理者です。あなたはRustプログラミングを支援する本システムのAIアシスタントであり、`{macro_name}` 手続きマクロを通じて呼び出されます。ユーザーの入力に基づいてRustコードを `{macro_name}` マクロの出力として生成してほしいです。コードはRustのベストプラクティスに従うようにしてください。{comment_instruction}回答はすべて `String` 値として扱われ、`proc_macro2::TokenStream` に変換されるため、回答は有効なRustコードである必要があります。**Rustコード以外のものはすべてコメント内に記述する必要があり、Rustコードとして変換しようとするとエラーになるものを出力してはなりません。そして、マクロ以外のユーザー入力はそのまま残るため、重複などをしないように注意してください。(たとえば、 `main` 関数を出力すると、ユーザー定義の `main` 関数と競合してコンパイルエラーが発生する可能性があります。あるいは、 `{macro_name}` マクロはmain関数の中からよばれているかもしれません。その時にmain関数ごと出力してはいけません。)**"#;
+const JAPANESE_COMMENTS: &str = "明確さを保つための日本語のコメントを含めるようにしてください。";
+const JAPANESE_HEADER: &str = r#"ここからは本システム利用者の入力になります:
+
+"#;
+const CHINESE_MESSAGE: &str = r#"我是本系统的管理员。你是本系统中协助 Rust 编程的 AI 助手，通过 `{macro_name}` 过程宏被调用。请根据用户的输入生成 Rust 代码，作为 `{macro_name}` 宏的输出。代码应符合 Rust 的惯用写法和最佳实践。{comment_instruction}你的所有回答都会被当作 `String` 值并转换为 `proc_macro2::TokenStream`，因此回答必须是有效的 Rust 代码。**所有非 Rust 代码的内容都必须写在注释中，不得输出任何会导致转换失败的内容。此外，宏以外的用户输入会保持原样，请注意不要产生重复。(例如，如果输出 `main` 函数，可能会与用户定义的 `main` 函数冲突而导致编译错误。或者 `{macro_name}` 宏可能是在 `main` 函数内部调用的，这时不应输出整个 `main` 函数。)**"#;
+const CHINESE_COMMENTS: &str = "请添加简体中文注释以保持代码清晰。";
+const CHINESE_HEADER: &str = r#"以下是本系统用户的输入:
+
+"#;
+const KOREAN_MESSAGE: &str = r#"저는 이 시스템의 관리자입니다. 당신은 Rust 프로그래밍을 돕는 이 시스템의 AI 어시스턴트이며, `{macro_name}` 절차적 매크로를 통해 호출됩니다. 사용자의 입력을 바탕으로 Rust 코드를 `{macro_name}` 매크로의 출력으로 생성해 주세요. 코드는 Rust의 관용적인 방식과 모범 사례를 따라야 합니다. {comment_instruction} 모든 답변은 `String` 값으로 취급되어 `proc_macro2::TokenStream` 으로 변환되므로, 답변은 유효한 Rust 코드여야 합니다. **Rust 코드가 아닌 모든 내용은 주석 안에 작성해야 하며, 변환을 방해하는 내용을 출력해서는 안 됩니다. 또한 매크로 이외의 사용자 입력은 그대로 남으므로 중복되지 않도록 주의하세요. (예를 들어 `main` 함수를 출력하면 사용자가 정의한 `main` 함수와 충돌하여 컴파일 오류가 발생할 수 있습니다. 또는 `{macro_name}` 매크로가 `main` 함수 안에서 호출되었을 수 있으며, 이 경우 `main` 함수 전체를 출력해서는 안 됩니다.)**"#;
+const KOREAN_COMMENTS: &str = "명확성을 위해 한국어 주석을 포함해 주세요.";
+const KOREAN_HEADER: &str = r#"다음은 이 시스템 사용자의 입력입니다:
+
+"#;
+const GERMAN_MESSAGE: &str = r#"Ich bin der Administrator dieses Systems. Du bist ein KI-Assistent dieses Systems, der bei der Rust-Programmierung hilft, und wirst über das prozedurale Makro `{macro_name}` aufgerufen. Erzeuge auf Grundlage der Eingabe des Benutzers Rust-Code als Ausgabe des Makros `{macro_name}`. Der Code soll idiomatisch sein und den Best Practices von Rust folgen. {comment_instruction} Alle deine Antworten werden als `String`-Werte behandelt und in `proc_macro2::TokenStream` umgewandelt, daher müssen deine Antworten gültiger Rust-Code sein. **Alles, was kein Rust-Code ist, muss in einem Kommentar stehen, und du darfst nichts ausgeben, was die Umwandlung verhindern würde. Die Benutzereingabe außerhalb des Makros bleibt erhalten, achte also darauf, nichts doppelt zu erzeugen. (Wenn du zum Beispiel eine `main`-Funktion ausgibst, kann sie mit einer vom Benutzer definierten `main`-Funktion kollidieren und einen Kompilierfehler verursachen. Oder das Makro `{macro_name}` wird innerhalb der `main`-Funktion aufgerufen; in diesem Fall darfst du nicht die ganze `main`-Funktion ausgeben.)**"#;
+const GERMAN_COMMENTS: &str = "Füge zur Verständlichkeit Kommentare auf Deutsch hinzu.";
+const GERMAN_HEADER: &str = r#"Es folgen die Eingaben des Benutzers dieses Systems:
+
+"#;
+
+/// 組み込みのロケール (言語コード, システムメッセージ, コメントの言語の指示, ユーザー入力の前置き)
+const LOCALES: &[(&str, &str, &str, &str)] = &[
+    ("en", ENGLISH_MESSAGE, ENGLISH_COMMENTS, ENGLISH_HEADER),
+    ("ja", JAPANESE_MESSAGE, JAPANESE_COMMENTS, JAPANESE_HEADER),
+    ("zh", CHINESE_MESSAGE, CHINESE_COMMENTS, CHINESE_HEADER),
+    ("ko", KOREAN_MESSAGE, KOREAN_COMMENTS, KOREAN_HEADER),
+    ("de", GERMAN_MESSAGE, GERMAN_COMMENTS, GERMAN_HEADER),
+];
+
+/// マクロ名とデフォルトの言語の対応
+const MACROS: &[(&str, &str)] = &[("do_it", "en"), ("あとは任せた", "ja")];
+
+pub fn is_known_macro(macro_name: &str) -> bool {
+    MACROS.iter().any(|(name, _)| *name == macro_name)
+}
+
+/// 1言語分のシステムメッセージ
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Locale {
+    message: String,
+    comment_instruction: String,
+    header: String,
+}
+
+impl Locale {
+    /// 組み込みのロケールに設定ファイルの `[locales.<lang>]` を重ねる
+    fn resolve(lang: &str, locales: &BTreeMap<String, LocaleConfig>) -> anyhow::Result<Self> {
+        let builtin = LOCALES.iter().find(|(code, ..)| *code == lang);
+        let custom = locales.get(lang);
+        let (message, comment_instruction, header) =
+            match (builtin, custom) {
+                (Some((_, message, comments, header)), _) => (*message, *comments, *header),
+                // 新しい言語はコメントの指示さえあれば英語のメッセージを土台にする
+                (
+                    None,
+                    Some(LocaleConfig {
+                        comment_instruction: Some(_),
+                        ..
+                    }),
+                ) => (ENGLISH_MESSAGE, "", ENGLISH_HEADER),
+                (None, Some(_)) => bail!("locale `{}` must set `comment_instruction`", lang),
+                (None, None) => {
+                    let available =
+                        LOCALES
+                            .iter()
+                            .map(|(code, ..)| *code)
+                            .chain(locales.keys().map(String::as_str).filter(|code| {
+                                !LOCALES.iter().any(|(builtin, ..)| builtin == code)
+                            }))
+                            .collect::<Vec<_>>();
+                    bail!(
+                        "unknown lang `{}` (available: {})",
+                        lang,
+                        available.join(", ")
+                    );
+                }
+            };
+
+        let custom = custom.cloned().unwrap_or_default();
+        Ok(Self {
+            message: custom.message.unwrap_or(message.to_string()),
+            comment_instruction: custom
+                .comment_instruction
+                .unwrap_or(comment_instruction.to_string()),
+            header: custom.header.unwrap_or(header.to_string()),
+        })
+    }
+}
+
+/// 設定を反映したシステムメッセージを組み立てる
+pub fn build_system_message(macro_name: &str, settings: &Settings) -> anyhow::Result<String> {
+    let config = Config::load()?;
+    let message = compose(macro_name, settings, &config.locales)?;
+    Ok(expand_placeholders(
+        &message,
+        macro_name,
+        &CrateInfo::load(),
+    ))
+}
+
+fn compose(
+    macro_name: &str,
+    settings: &Settings,
+    locales: &BTreeMap<String, LocaleConfig>,
+) -> anyhow::Result<String> {
+    let Some((_, default_lang)) = MACROS.iter().find(|(name, _)| *name == macro_name) else {
+        bail!("unknown macro `{}`", macro_name);
+    };
+    let lang = settings.lang.as_deref().unwrap_or(default_lang);
+    let locale =
+        Locale::resolve(lang, locales).with_context(|| format!("invalid lang `{}`", lang))?;
+    let message = locale
+        .message
+        .replace("{comment_instruction}", &locale.comment_instruction);
+
+    let Some(custom) = settings.custom_system_prompt()? else {
+        return Ok(format!("{} {}", message, locale.header));
+    };
+
+    Ok(match settings.system_prompt_mode() {
+        PromptMode::Replace => custom,
+        PromptMode::Extend => format!("{}\n\n{}\n\n{}", message, custom.trim(), locale.header),
+    })
+}
+
+/// `{macro_name}`, `{crate_name}`, `{edition}`, `{features}` を展開する
+///
+/// Rustのコードを含むこともあるので、それ以外の `{...}` はそのまま残す
+fn expand_placeholders(template: &str, macro_name: &str, crate_info: &CrateInfo) -> String {
+    let features = match crate_info.features.is_empty() {
+        true => "(none)".to_string(),
+        false => crate_info.features.join(", "),
+    };
+    template
+        .replace("{macro_name}", macro_name)
+        .replace("{crate_name}", &crate_info.name)
+        .replace("{edition}", &crate_info.edition)
+        .replace("{features}", &features)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_placeholders() {
+        let crate_info = CrateInfo {
+            name: "my_crate".to_string(),
+            edition: "2021".to_string(),
+            features: vec!["std".to_string(), "serde".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            expand_placeholders(
+                "`{macro_name}` in {crate_name} (edition {edition}, features: {features}). Keep {this}.",
+                "do_it",
+                &crate_info
+            ),
+            "`do_it` in my_crate (edition 2021, features: std, serde). Keep {this}."
+        );
+    }
+
+    #[test]
+    fn test_extend_builtin_message() {
+        let settings = Settings {
+            system_prompt: Some("Never use `unsafe`.".to_string()),
+            system_prompt_mode: Some(PromptMode::Extend),
+            ..Default::default()
+        };
+        let message = compose("do_it", &settings, &BTreeMap::new()).unwrap();
+        assert!(message.starts_with("I'm the administrator of this system."));
+        assert!(message.ends_with(&format!("Never use `unsafe`.\n\n{}", ENGLISH_HEADER)));
+    }
+
+    #[test]
+    fn test_locales() {
+        // あとは任せたは日本語のプリセット
+        let message = compose("あとは任せた", &Settings::default(), &BTreeMap::new()).unwrap();
+        assert!(message.contains(JAPANESE_COMMENTS));
+        assert!(message.ends_with(JAPANESE_HEADER));
+
+        let settings = Settings {
+            lang: Some("de".to_string()),
+            ..Default::default()
+        };
+        let message = compose("do_it", &settings, &BTreeMap::new()).unwrap();
+        assert!(message.contains(GERMAN_COMMENTS));
+        assert!(!message.contains("{comment_instruction}"));
+
+        // 設定ファイルから言語を追加する
+        let locales: BTreeMap<String, LocaleConfig> = toml::from_str(
+            r#"
+            [fr]
+            comment_instruction = "Ajoute des commentaires en français."
+
+            [ja]
+            header = "入力:\n"
+            "#,
+        )
+        .unwrap();
+        let settings = Settings {
+            lang: Some("fr".to_string()),
+            ..Default::default()
+        };
+        let message = compose("do_it", &settings, &locales).unwrap();
+        assert!(message.contains("Ajoute des commentaires en français."));
+        assert!(message.ends_with(ENGLISH_HEADER));
+
+        let message = compose("あとは任せた", &Settings::default(), &locales).unwrap();
+        assert!(message.contains(JAPANESE_COMMENTS));
+        assert!(message.ends_with("入力:\n"));
+
+        let settings = Settings {
+            lang: Some("xx".to_string()),
+            ..Default::default()
+        };
+        let err = compose("do_it", &settings, &locales).unwrap_err();
+        assert!(format!("{:#}", err).contains("available: en, ja, zh, ko, de, fr"));
+    }
+}
@@ -0,0 +1,303 @@
+//! 送信前にプロンプトのトークン数を見積もる
+//!
+//! tiktoken形式のBPEテーブル (`o200k_base.tiktoken` 等) を `tokenizer_file` か
+//! `HEY_GPT_TOKENIZER_FILE` で指定すると正確に数える。
+//! テーブルは同梱していないので、指定がなければ同じ前処理で区切った上で文字数から概算する。
+//! この場合の上限はあくまで目安で、上限付近では判定を誤ることがある
+
+use anyhow::{anyhow, bail, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use super::config::{manifest_dir, Config, Settings};
+use super::query::Message;
+
+/// メッセージごとに付く `<|start|>role ... <|end|>` 分
+const TOKENS_PER_MESSAGE: u64 = 4;
+/// 返答の先頭に付く分
+const TOKENS_PER_REPLY: u64 = 3;
+
+/// モデル名の前方一致とコンテキストウィンドウの大きさ (長いものから順に並べる)
+const CONTEXT_WINDOWS: &[(&str, u64)] = &[
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-preview", 128_000),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3-mini", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+];
+
+pub fn context_window(model: &str) -> Option<u64> {
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// コンテキストウィンドウから返答の分を引いたものと `max_prompt_tokens` の小さい方
+pub fn prompt_token_limit(settings: &Settings) -> Option<u64> {
+    let window = context_window(settings.model())
+        .map(|window| window.saturating_sub(settings.max_completion_tokens.unwrap_or(0)));
+    match (window, settings.max_prompt_tokens) {
+        (Some(window), Some(max)) => Some(window.min(max)),
+        (window, max) => window.or(max),
+    }
+}
+
+pub struct Tokenizer {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Tokenizer {
+    /// `<base64のトークン> <ランク>` の行からなるtiktoken形式のテーブルを読む
+    pub fn from_tiktoken(text: &str) -> anyhow::Result<Self> {
+        let mut ranks = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some((token, rank)) = line.split_once(' ') else {
+                bail!("line {}: expected `<base64> <rank>`", i + 1);
+            };
+            let token = STANDARD
+                .decode(token)
+                .with_context(|| format!("line {}: invalid base64", i + 1))?;
+            let rank = rank
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid rank", i + 1))?;
+            ranks.insert(token, rank);
+        }
+        Ok(Self { ranks })
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_tiktoken(&text).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    pub fn count(&self, text: &str) -> u64 {
+        pre_tokenize(text)
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+
+    /// 1つの区切りをバイト単位のBPEでマージしていった結果のトークン数
+    fn count_piece(&self, piece: &[u8]) -> u64 {
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+
+        // parts[i]..parts[i + 1] が1つのトークン
+        let mut parts = (0..=piece.len()).collect::<Vec<_>>();
+        while parts.len() > 2 {
+            let best = (0..parts.len() - 2)
+                .filter_map(|i| {
+                    self.ranks
+                        .get(&piece[parts[i]..parts[i + 2]])
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else {
+                break;
+            };
+            parts.remove(i + 1);
+        }
+        (parts.len() - 1) as u64
+    }
+}
+
+/// 設定されたテーブルを1度だけ読み込む。指定がなければ `None`
+fn tokenizer() -> anyhow::Result<Option<&'static Tokenizer>> {
+    static TOKENIZER: OnceLock<Result<Option<Tokenizer>, String>> = OnceLock::new();
+    let tokenizer = TOKENIZER.get_or_init(|| {
+        let path = env::var("HEY_GPT_TOKENIZER_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(Into::into)
+            .or_else(|| {
+                let config = Config::load().ok()?;
+                config.tokenizer_file.map(|path| manifest_dir().join(path))
+            });
+        path.map(|path| Tokenizer::load(&path))
+            .transpose()
+            .map_err(|err| format!("{:#}", err))
+    });
+    match tokenizer {
+        Ok(tokenizer) => Ok(tokenizer.as_ref()),
+        Err(err) => Err(anyhow!("{} (tokenizer_file / HEY_GPT_TOKENIZER_FILE)", err)),
+    }
+}
+
+/// 指定されたテーブルが読めなければエラーにする
+///
+/// 正確に数えているつもりで概算の上限を使い続けないよう、数える前に確かめる
+pub fn check_tokenizer() -> anyhow::Result<()> {
+    tokenizer().map(|_| ())
+}
+
+/// 読めないテーブルは [`check_tokenizer`] で報告するので、ここでは概算で続ける
+pub fn count_tokens(text: &str) -> u64 {
+    match tokenizer() {
+        Ok(Some(tokenizer)) => tokenizer.count(text),
+        _ => estimate_tokens(text),
+    }
+}
+
+pub fn count_message_tokens(messages: &[Message]) -> u64 {
+    messages
+        .iter()
+        .map(|message| TOKENS_PER_MESSAGE + count_tokens(&message.content))
+        .sum::<u64>()
+        + TOKENS_PER_REPLY
+}
+
+/// テーブルがない場合の概算 (ASCIIはおおよそ4文字で1トークン、それ以外は1文字1トークン)
+fn estimate_tokens(text: &str) -> u64 {
+    pre_tokenize(text)
+        .map(|piece| match piece.is_ascii() {
+            true => piece.len().div_ceil(4).max(1) as u64,
+            false => piece.chars().count() as u64,
+        })
+        .sum()
+}
+
+/// tiktokenの `cl100k_base` / `o200k_base` の正規表現による区切りを手で再現したもの
+///
+/// `'s|'t|'re|'ve|'m|'ll|'d|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+`
+fn pre_tokenize(text: &str) -> impl Iterator<Item = &str> {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start == chars.len() {
+            return None;
+        }
+        let end = start + piece_len(&chars[start..]);
+        let byte_offset = |n: usize| chars.get(n).map_or(text.len(), |(i, _)| *i);
+        let piece = &text[byte_offset(start)..byte_offset(end)];
+        start = end;
+        Some(piece)
+    })
+}
+
+/// 先頭の区切りの文字数
+fn piece_len(chars: &[(usize, char)]) -> usize {
+    let is_letter = |c: char| c.is_alphabetic();
+    let is_number = |c: char| c.is_numeric();
+    let is_newline = |c: char| c == '\r' || c == '\n';
+    let is_punct = |c: char| !c.is_whitespace() && !is_letter(c) && !is_number(c);
+    let count_while = |from: usize, pred: &dyn Fn(char) -> bool| {
+        chars[from..].iter().take_while(|(_, c)| pred(*c)).count()
+    };
+
+    let first = chars[0].1;
+    let second = chars.get(1).map(|(_, c)| *c);
+
+    // 's 't 're 've 'm 'll 'd
+    if first == '\'' {
+        let next = chars[1..]
+            .iter()
+            .take(2)
+            .map(|(_, c)| c.to_ascii_lowercase())
+            .collect::<String>();
+        for suffix in ["re", "ve", "ll", "s", "t", "m", "d"] {
+            if next.starts_with(suffix) {
+                return 1 + suffix.len();
+            }
+        }
+    }
+
+    // [^\r\n\p{L}\p{N}]?\p{L}+
+    if is_letter(first) {
+        return count_while(0, &is_letter);
+    }
+    if !is_newline(first) && !is_number(first) && second.is_some_and(is_letter) {
+        return 1 + count_while(1, &is_letter);
+    }
+
+    // \p{N}{1,3}
+    if is_number(first) {
+        return count_while(0, &is_number).min(3);
+    }
+
+    // ' '?[^\s\p{L}\p{N}]+[\r\n]*
+    let start = match first == ' ' && second.is_some_and(is_punct) {
+        true => 1,
+        false => 0,
+    };
+    if is_punct(chars[start].1) {
+        let end = start + count_while(start, &is_punct);
+        return end + count_while(end, &is_newline);
+    }
+
+    // 残りは空白の連続
+    let spaces = count_while(0, &|c: char| c.is_whitespace());
+    // \s*[\r\n]+
+    if let Some(last_newline) = chars[..spaces].iter().rposition(|(_, c)| is_newline(*c)) {
+        return last_newline + 1;
+    }
+    // \s+(?!\S) : 次の単語の前の空白1つは単語側に付ける
+    if spaces > 1 && spaces < chars.len() {
+        return spaces - 1;
+    }
+    spaces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pre_tokenize() {
+        assert_eq!(
+            pre_tokenize("fn main() {\n    println!(\"I'm 12345\");\n}").collect::<Vec<_>>(),
+            vec![
+                "fn", " main", "()", " {\n", "   ", " println", "!(\"", "I", "'m", " ", "123",
+                "45", "\");\n", "}"
+            ]
+        );
+        assert_eq!(
+            pre_tokenize("こんにちは 世界").collect::<Vec<_>>(),
+            vec!["こんにちは", " 世界"]
+        );
+    }
+
+    #[test]
+    fn test_bpe() {
+        // a, b, c と ab, abc だけを持つテーブル
+        let table = ["a", "b", "c", "ab", "abc", " "]
+            .iter()
+            .enumerate()
+            .map(|(rank, token)| format!("{} {}", STANDARD.encode(token), rank))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let tokenizer = Tokenizer::from_tiktoken(&table).unwrap();
+
+        assert_eq!(tokenizer.count("abc"), 1);
+        assert_eq!(tokenizer.count("abcab"), 2);
+        assert_eq!(tokenizer.count("cba"), 3);
+
+        assert!(Tokenizer::from_tiktoken("YWJj").is_err());
+    }
+
+    #[test]
+    fn test_context_window() {
+        assert_eq!(context_window("gpt-4o-2024-08-06"), Some(128_000));
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("o1"), Some(200_000));
+        assert_eq!(context_window("my-local-model"), None);
+    }
+}
@@ -0,0 +1,270 @@
+use fs4::fs_std::FileExt;
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const DEFAULT_CODE_TAGS: &[&str] = &["rust", "rs"];
+
+/// 言語名のないコードブロックの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UntaggedPolicy {
+    /// 言語名のあるブロックがない時だけ使う
+    #[default]
+    Fallback,
+    /// 言語名のあるブロックと一緒に常に使う
+    Include,
+    /// 使わない
+    Ignore,
+}
+
+impl FromStr for UntaggedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fallback" => Ok(Self::Fallback),
+            "include" => Ok(Self::Include),
+            "ignore" => Ok(Self::Ignore),
+            _ => Err(format!(
+                "unknown untagged fence policy `{}` (expected \"fallback\", \"include\" or \"ignore\")",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Rustのコードとして扱う言語名 (大文字小文字は区別しない)
+    pub tags: Vec<String>,
+    pub untagged: UntaggedPolicy,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            tags: DEFAULT_CODE_TAGS.iter().map(|t| t.to_string()).collect(),
+            untagged: UntaggedPolicy::default(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    fn is_target(&self, info: &str) -> bool {
+        // `rust,ignore` や `rust edition2021` のような属性付きのものは先頭の言語名だけを見る
+        let lang = info
+            .split(|c: char| c == ',' || c == '{' || c.is_whitespace())
+            .next()
+            .unwrap_or("");
+        self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(lang))
+    }
+}
+
+pub fn extract_rust_codes(markdown: &str, options: &ExtractOptions) -> Vec<String> {
+    let mut blocks = Vec::new();
+    // (タグ付きか, 現在のブロック)
+    let mut current: Option<(bool, String)> = None;
+
+    // 閉じられていないフェンスはCommonMarkの仕様通り文書末尾で閉じられる
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let info = info.trim();
+                if info.is_empty() {
+                    current = Some((false, String::new()));
+                } else if options.is_target(info) {
+                    current = Some((true, String::new()));
+                }
+            }
+            Event::Text(text) => {
+                if let Some((_, block)) = current.as_mut() {
+                    block.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some(block) = current.take() {
+                    blocks.push(block);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let has_tagged = blocks.iter().any(|(tagged, _)| *tagged);
+    blocks
+        .into_iter()
+        .filter(|(tagged, _)| match options.untagged {
+            UntaggedPolicy::Include => true,
+            UntaggedPolicy::Ignore => *tagged,
+            UntaggedPolicy::Fallback => *tagged == has_tagged,
+        })
+        .map(|(_, block)| block)
+        .collect()
+}
+
+/// 同じディレクトリの一時ファイルに書いてから名前を変える。読み手には書きかけのファイルが見えず、同時に書いても壊れない
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
+}
+
+/// 排他ロックを取ったファイルを返す。他のプロセスが持っていれば解放されるまで待ち、閉じると解放される
+pub fn lock_file(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // 待っている相手が別のファイルをロックすることにならないよう、ロックファイルは消さない
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_rust_codes, lock_file, write_atomic, ExtractOptions, UntaggedPolicy};
+    use std::time::{Duration, Instant};
+    use std::{env, fs, process, thread};
+
+    #[test]
+    fn test_extract_rust_codes() {
+        let input = r#"
+aaaa        
+        
+```rust
+fn main() {
+    println!("Hello, world!");
+}
+```
+
+bbbbb
+
+```rust
+fn hoge() {
+    println!("hoge");
+}
+```
+
+cccccc"#;
+        let expected = vec![
+            r#"fn main() {
+    println!("Hello, world!");
+}
+"#
+            .to_string(),
+            r#"fn hoge() {
+    println!("hoge");
+}
+"#
+            .to_string(),
+        ];
+
+        let res = extract_rust_codes(input, &ExtractOptions::default());
+        assert_eq!(res, expected);
+    }
+
+    /// `fixtures/extract/<name>.md` をモデル出力、 `<name>.rs` を期待値とする
+    /// (期待値のブロック同士は `// ----` 行で区切る)
+    fn check_fixture(markdown: &str, expected: &str, options: &ExtractOptions) {
+        let expected = expected
+            .split("// ----\n")
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>();
+        let res = extract_rust_codes(markdown, options);
+        assert_eq!(res, expected);
+    }
+
+    macro_rules! fixture {
+        ($name:ident) => {
+            fixture!($name, ExtractOptions::default());
+        };
+        ($name:ident, $options:expr) => {
+            #[test]
+            fn $name() {
+                check_fixture(
+                    include_str!(concat!("fixtures/extract/", stringify!($name), ".md")),
+                    include_str!(concat!("fixtures/extract/", stringify!($name), ".rs")),
+                    &$options,
+                );
+            }
+        };
+    }
+
+    fixture!(rs_tag);
+    fixture!(capitalized_tag);
+    fixture!(attributed_tag);
+    fixture!(tilde_fence);
+    fixture!(four_backticks);
+    fixture!(unterminated);
+    fixture!(nested_in_list);
+    fixture!(untagged_fallback);
+    fixture!(mixed_with_other_languages);
+    fixture!(
+        untagged_ignored,
+        ExtractOptions {
+            untagged: UntaggedPolicy::Ignore,
+            ..Default::default()
+        }
+    );
+    fixture!(
+        untagged_included,
+        ExtractOptions {
+            untagged: UntaggedPolicy::Include,
+            ..Default::default()
+        }
+    );
+    fixture!(
+        custom_tags,
+        ExtractOptions {
+            tags: vec!["rust2021".to_string()],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn test_write_atomic_and_lock_file() {
+        let dir = env::temp_dir().join(format!("hey_gpt_util_test_{}", process::id()));
+        let path = dir.join("nested/entry.txt");
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        // 一時ファイルは残らない
+        assert_eq!(fs::read_dir(dir.join("nested")).unwrap().count(), 1);
+
+        // 先にロックを取った側が閉じるまで待たされる
+        let lock_path = dir.join("locks/key.lock");
+        let lock = lock_file(&lock_path).unwrap();
+        let start = Instant::now();
+        let waiter = thread::spawn(move || {
+            let _lock = lock_file(&lock_path).unwrap();
+            start.elapsed()
+        });
+        thread::sleep(Duration::from_millis(200));
+        drop(lock);
+        assert!(waiter.join().unwrap() >= Duration::from_millis(200));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::report::format_day;
+use hey_chat_gpt_core::audit::{audit_path, AuditEntry, Outcome};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut file = None;
+    let mut model = None;
+    let mut outcome = None;
+    let mut since = None;
+    let mut json = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .with_context(|| format!("{} requires a value", name))
+        };
+        match arg.as_str() {
+            "--manifest-dir" => manifest_dir = Some(PathBuf::from(value(arg)?)),
+            "--file" => file = Some(value(arg)?),
+            "--model" => model = Some(value(arg)?),
+            "--outcome" => {
+                outcome = Some(value(arg)?.parse::<Outcome>().map_err(anyhow::Error::msg)?)
+            }
+            "--since" => since = Some(value(arg)?),
+            "--json" => json = true,
+            _ => bail!("unknown option `{}`", arg),
+        }
+    }
+    crate::set_manifest_dir(manifest_dir)?;
+
+    let path = audit_path();
+    let entries = load_audit_log(&path)?
+        .into_iter()
+        .filter(|entry| {
+            file.as_ref()
+                .is_none_or(|file| entry.file.contains(file.as_str()))
+        })
+        .filter(|entry| {
+            model
+                .as_ref()
+                .is_none_or(|model| entry.model.starts_with(model.as_str()))
+        })
+        .filter(|entry| outcome.is_none_or(|outcome| entry.outcome == outcome))
+        // YYYY-MM-DD なので文字列のまま比べられる
+        .filter(|entry| {
+            since
+                .as_ref()
+                .is_none_or(|since| format_day(entry.timestamp) >= *since)
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        for entry in &entries {
+            println!("{}", serde_json::to_string(entry)?);
+        }
+        return Ok(());
+    }
+
+    println!("# Audit log: {}", path.display());
+    if entries.is_empty() {
+        println!("No matching entries");
+        return Ok(());
+    }
+    let sent = entries
+        .iter()
+        .filter(|entry| entry.outcome != Outcome::Hit)
+        .count();
+    println!(
+        "{} entries ({} sent to the API, {} served from the cache)",
+        entries.len(),
+        sent,
+        entries.len() - sent
+    );
+    println!();
+    for entry in &entries {
+        println!(
+            "{}  {:<6}  {}  {}  {}  request={}  response={}",
+            format_time(entry.timestamp),
+            format!("{:?}", entry.outcome).to_lowercase(),
+            entry.file,
+            entry.model,
+            entry.endpoint,
+            entry
+                .request_sha256
+                .as_deref()
+                .map_or("-", |hash| &hash[..hash.len().min(12)]),
+            entry.response_id.as_deref().unwrap_or("-"),
+        );
+    }
+
+    Ok(())
+}
+
+pub fn load_audit_log(path: &Path) -> anyhow::Result<Vec<AuditEntry>> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Ok(Vec::new());
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: invalid entry", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// UNIX時刻を `YYYY-MM-DD hh:mm:ss` (UTC) にする
+fn format_time(timestamp: u64) -> String {
+    let seconds = timestamp % 86400;
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        format_day(timestamp),
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(1_735_689_599), "2024-12-31 23:59:59");
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::PathBuf;
+
+use hey_chat_gpt_core::config::{Config, Settings};
+use hey_chat_gpt_core::locate::find_invocations;
+use hey_chat_gpt_core::MacroInput;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut source_file_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            _ if arg.starts_with('-') => bail!("unknown option `{}`", arg),
+            _ => source_file_path = Some(PathBuf::from(arg)),
+        }
+    }
+    crate::set_manifest_dir(manifest_dir)?;
+
+    let config = Config::load()?;
+    match &config.source {
+        Some(source) => println!("# Loaded from {}", source.display()),
+        None => println!("# No configuration file"),
+    }
+    println!("# cache_dir = {}", config.cache_dir().display());
+    if let Some(cache_url) = config.cache_url() {
+        println!("# cache_url = {} ({:?})", cache_url, config.cache_mode()?);
+    }
+    if config.state_dir() != config.cache_dir() {
+        println!(
+            "# generated files, ledger and audit log in {}",
+            config.state_dir().display()
+        );
+    }
+
+    let settings = match &source_file_path {
+        Some(path) => {
+            let mut settings = config.settings_for(path)?;
+            // ファイル中の最初の呼び出しのマクロ引数も重ねる
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if let Some((macro_name, tokens)) = find_invocations(&content).into_iter().next() {
+                let input = syn::parse2::<MacroInput>(tokens)
+                    .with_context(|| format!("failed to parse `{}!`", macro_name))?;
+                println!("# Including the arguments of `{}!`", macro_name);
+                settings.merge(&input.settings);
+            }
+            settings
+        }
+        None => {
+            let mut settings = config.settings.clone();
+            settings.merge(&Settings::from_env()?);
+            settings
+        }
+    };
+
+    print!("{}", toml::to_string_pretty(&effective(&settings))?);
+
+    Ok(())
+}
+
+/// デフォルト値を埋めたもの
+fn effective(settings: &Settings) -> Settings {
+    let extract_options = settings.extract_options();
+    Settings {
+        provider: Some(settings.provider().to_string()),
+        model: Some(settings.model().to_string()),
+        endpoint: Some(settings.endpoint().to_string()),
+        max_retries: Some(settings.max_retries()),
+        timeout_secs: Some(settings.timeout().as_secs()),
+        code_tags: Some(extract_options.tags),
+        untagged_fences: Some(extract_options.untagged),
+        offline: Some(settings.offline()),
+        pin: Some(settings.pin()),
+        context: Some(settings.context()),
+        context_max_tokens: Some(settings.context_max_tokens()),
+        system_prompt_mode: Some(settings.system_prompt_mode()),
+        ..settings.clone()
+    }
+}
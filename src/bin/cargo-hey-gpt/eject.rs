@@ -0,0 +1,405 @@
+use anyhow::{bail, Context};
+use proc_macro2::LineColumn;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+
+use crate::report::format_day;
+use hey_chat_gpt_core::cache::{get_cache_file_path, load_cache, load_entry_meta};
+use hey_chat_gpt_core::config::{self, ContextMode};
+use hey_chat_gpt_core::locate::find_rust_files;
+use hey_chat_gpt_core::pin::{Lock, LOCK_FILE_NAME};
+use hey_chat_gpt_core::util::write_atomic;
+use hey_chat_gpt_core::{
+    build_system_message, cached_redactions, extract_code, is_known_macro, request_key,
+    standalone_content, strip_private, MacroInput,
+};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut target = None;
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            "--dry-run" => dry_run = true,
+            _ if arg.starts_with('-') => bail!("unknown option `{}`", arg),
+            _ => target = Some(PathBuf::from(arg)),
+        }
+    }
+    let manifest_dir = crate::set_manifest_dir(manifest_dir)?;
+
+    let files = match target {
+        Some(path) if path.is_dir() => find_rust_files(&path)?,
+        Some(path) => vec![path],
+        None => find_rust_files(&manifest_dir)?,
+    };
+
+    let mut ejected = 0;
+    for path in files {
+        ejected += eject_file(&path, dry_run)?;
+    }
+    match (ejected, dry_run) {
+        (0, _) => eprintln!("Nothing to eject"),
+        (_, true) => eprintln!("{} invocation(s) would be ejected", ejected),
+        (_, false) => eprintln!(
+            "Ejected {} invocation(s); remove `hey_chat_gpt` from Cargo.toml once nothing uses it",
+            ejected
+        ),
+    }
+
+    Ok(())
+}
+
+/// ファイル中の呼び出しをキャッシュされたコードに置き換え、置き換えた数を返す
+fn eject_file(path: &Path, dry_run: bool) -> anyhow::Result<usize> {
+    let raw_content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    // キャッシュのキーはマクロ展開時と同じく置き換える前の内容から作る
+    let file_content = strip_private(&raw_content);
+
+    let lock = Lock::load()?;
+    let mut replacements = Vec::new();
+    for invocation in find_invocation_ranges(&raw_content) {
+        let location = format!(
+            "{}:{}",
+            path.display(),
+            raw_content[..invocation.range.start].matches('\n').count() + 1
+        );
+        let input = syn::parse2::<MacroInput>(invocation.tokens.clone())
+            .with_context(|| format!("failed to parse `{}!` at {}", invocation.name, location))?;
+        let settings = config::resolve(path, &input.settings)
+            .with_context(|| format!("invalid settings for {}", location))?;
+        let content = match settings.context() {
+            ContextMode::None => standalone_content(&invocation.name, &input)?,
+            _ => file_content.clone(),
+        };
+        let system_message = build_system_message(&invocation.name, &settings)?;
+        let key = request_key(&input, &settings, &system_message, path, &content);
+        let pinned = match settings.pin() {
+            true => lock.find(path, &invocation.name, &invocation.tokens)?,
+            false => None,
+        };
+        // モデルと時刻は今の設定やファイルではなく、生成した時にキャッシュへ記録したものを使う
+        let meta = load_entry_meta(&get_cache_file_path(&key));
+        let model = meta.model.unwrap_or_else(|| "unknown".to_string());
+        let (code, generated) = if let Some(pinned) = pinned {
+            // ロックファイルにもキャッシュと同じく伏せたまま記録されている
+            let pinned = cached_redactions(&settings, path, &content)?.restore(pinned);
+            (pinned, format!("pinned in {}", LOCK_FILE_NAME))
+        } else {
+            let Some(cache) = load_cache(&key)? else {
+                eprintln!(
+                    "    Skipped {}: no cached generation (build or run `cargo hey-gpt prefetch` first)",
+                    location
+                );
+                continue;
+            };
+
+            // キャッシュには伏せたまま保存されているので、マクロ展開時と同じく元の値に戻す
+            let cache = cached_redactions(&settings, path, &content)?.restore(&cache);
+            (
+                extract_code(&cache, &settings.extract_options()),
+                format_day(meta.created),
+            )
+        };
+        let code = match syn::parse_file(&code) {
+            Ok(file) => prettyplease::unparse(&file),
+            Err(_) => format!("{}\n", code.trim_end()),
+        };
+
+        let replacement = format!(
+            "// Ejected from `{}!` (model: {}, generated: {}, cache key: {})\n{}",
+            invocation.name, model, generated, key, code
+        );
+        let indent = line_indent(&raw_content, invocation.range.start);
+        replacements.push((invocation.range, indent_lines(&replacement, indent)));
+        eprintln!("    Ejected {}", location);
+    }
+
+    if !dry_run && !replacements.is_empty() {
+        let mut ejected = raw_content.clone();
+        // 後ろから置き換えれば前の位置はずれない
+        for (range, replacement) in replacements.iter().rev() {
+            ejected.replace_range(range.clone(), replacement.trim_end());
+        }
+        // 呼び出しが残っていなければ、使われなくなったマクロのインポートも消す
+        if find_invocation_ranges(&ejected).is_empty() {
+            ejected = remove_macro_imports(&ejected);
+        }
+        write_atomic(path, &ejected)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    Ok(replacements.len())
+}
+
+struct Invocation {
+    name: String,
+    tokens: proc_macro2::TokenStream,
+    /// 末尾の `;` を含む範囲
+    range: Range<usize>,
+}
+
+/// アイテムや文の位置にある `do_it!` / `あとは任せた!` の呼び出しとその範囲を探す
+fn find_invocation_ranges(content: &str) -> Vec<Invocation> {
+    struct MacroVisitor<'a> {
+        content: &'a str,
+        invocations: Vec<Invocation>,
+    }
+
+    impl MacroVisitor<'_> {
+        fn push(&mut self, mac: &syn::Macro, span: proc_macro2::Span) {
+            let Some(segment) = mac.path.segments.last() else {
+                return;
+            };
+            let name = segment.ident.to_string();
+            if !is_known_macro(&name) {
+                return;
+            }
+            let (Some(start), Some(end)) = (
+                offset(self.content, span.start()),
+                offset(self.content, span.end()),
+            ) else {
+                return;
+            };
+            self.invocations.push(Invocation {
+                name,
+                tokens: mac.tokens.clone(),
+                range: start..end,
+            });
+        }
+    }
+
+    impl<'ast> Visit<'ast> for MacroVisitor<'_> {
+        fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+            self.push(&item.mac, item.span());
+        }
+
+        fn visit_stmt_macro(&mut self, stmt: &'ast syn::StmtMacro) {
+            self.push(&stmt.mac, stmt.span());
+        }
+    }
+
+    let Ok(file) = syn::parse_file(content) else {
+        return Vec::new();
+    };
+    let mut visitor = MacroVisitor {
+        content,
+        invocations: Vec::new(),
+    };
+    visitor.visit_file(&file);
+    visitor
+        .invocations
+        .sort_by_key(|invocation| invocation.range.start);
+    visitor.invocations
+}
+
+/// `use hey_chat_gpt::do_it;` などマクロのインポートを取り除く。他の名前は残し、空になった `use` は行ごと消す
+fn remove_macro_imports(content: &str) -> String {
+    struct UseVisitor<'a> {
+        content: &'a str,
+        edits: Vec<(Range<usize>, String)>,
+    }
+
+    impl<'ast> Visit<'ast> for UseVisitor<'_> {
+        fn visit_item_use(&mut self, item: &'ast syn::ItemUse) {
+            let mut stripped = item.clone();
+            let removed = match strip_macro_names(&mut stripped.tree, false) {
+                Strip::Unchanged => return,
+                Strip::Removed => true,
+                Strip::Changed => false,
+            };
+            let (Some(mut start), Some(mut end)) = (
+                offset(self.content, item.span().start()),
+                offset(self.content, item.span().end()),
+            ) else {
+                return;
+            };
+            // 属性 (`#[allow(unused)]` など) も含める
+            if let Some(attr) = item.attrs.first() {
+                start = offset(self.content, attr.span().start()).unwrap_or(start);
+            }
+            let replacement = if removed {
+                let line_start = self.content[..start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = self.content[end..]
+                    .find('\n')
+                    .map_or(self.content.len(), |i| end + i + 1);
+                // 同じ行に他のコードがなければ行ごと消す
+                if self.content[line_start..start].trim().is_empty()
+                    && self.content[end..line_end].trim().is_empty()
+                {
+                    start = line_start;
+                    end = line_end;
+                }
+                String::new()
+            } else {
+                let file = syn::File {
+                    shebang: None,
+                    attrs: Vec::new(),
+                    items: vec![syn::Item::Use(stripped)],
+                };
+                prettyplease::unparse(&file).trim_end().to_string()
+            };
+            self.edits.push((start..end, replacement));
+        }
+    }
+
+    let Ok(file) = syn::parse_file(content) else {
+        return content.to_string();
+    };
+    let mut visitor = UseVisitor {
+        content,
+        edits: Vec::new(),
+    };
+    visitor.visit_file(&file);
+    visitor.edits.sort_by_key(|(range, _)| range.start);
+
+    let mut content = content.to_string();
+    for (range, replacement) in visitor.edits.into_iter().rev() {
+        content.replace_range(range, &replacement);
+    }
+    content
+}
+
+enum Strip {
+    Unchanged,
+    Changed,
+    /// 木全体が不要になった
+    Removed,
+}
+
+/// `hey_chat_gpt` の下にあるマクロの名前を取り除く。 `in_crate` は `hey_chat_gpt::` の下にいるか
+fn strip_macro_names(tree: &mut syn::UseTree, in_crate: bool) -> Strip {
+    match tree {
+        syn::UseTree::Path(path) => {
+            let in_crate = in_crate || path.ident == "hey_chat_gpt";
+            strip_macro_names(&mut path.tree, in_crate)
+        }
+        syn::UseTree::Name(name) if in_crate && is_known_macro(&name.ident.to_string()) => {
+            Strip::Removed
+        }
+        syn::UseTree::Rename(rename) if in_crate && is_known_macro(&rename.ident.to_string()) => {
+            Strip::Removed
+        }
+        syn::UseTree::Group(group) => {
+            let mut changed = false;
+            let items = std::mem::take(&mut group.items);
+            for mut item in items {
+                match strip_macro_names(&mut item, in_crate) {
+                    Strip::Removed => changed = true,
+                    Strip::Changed => {
+                        changed = true;
+                        group.items.push(item);
+                    }
+                    Strip::Unchanged => group.items.push(item),
+                }
+            }
+            match (changed, group.items.is_empty()) {
+                (_, true) => Strip::Removed,
+                (true, false) => Strip::Changed,
+                (false, false) => Strip::Unchanged,
+            }
+        }
+        _ => Strip::Unchanged,
+    }
+}
+
+/// 行 (1始まり) と列 (0始まり、文字単位) をバイト位置にする
+fn offset(content: &str, position: LineColumn) -> Option<usize> {
+    let line_start = match position.line {
+        0 => return None,
+        1 => 0,
+        line => content.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+    let column = content[line_start..]
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([content.len() - line_start])
+        .nth(position.column)?;
+    Some(line_start + column)
+}
+
+fn line_indent(content: &str, offset: usize) -> &str {
+    let line_start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &content[line_start..offset];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// 1行目は呼び出しの位置に入るので、2行目以降だけを字下げする
+fn indent_lines(text: &str, indent: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) | (_, true) => format!("{}\n", line),
+            _ => format!("{}{}\n", indent, line),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_invocation_ranges() {
+        let content = r#"use hey_chat_gpt::do_it;
+
+do_it!(pub, "helpers");
+
+fn main() {
+    hey_chat_gpt::あとは任せた!("ここも");
+    let x = 1;
+}
+"#;
+        let invocations = find_invocation_ranges(content);
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(invocations[0].name, "do_it");
+        assert_eq!(
+            &content[invocations[0].range.clone()],
+            r#"do_it!(pub, "helpers");"#
+        );
+        assert_eq!(invocations[1].name, "あとは任せた");
+        assert_eq!(
+            &content[invocations[1].range.clone()],
+            r#"hey_chat_gpt::あとは任せた!("ここも");"#
+        );
+        assert_eq!(line_indent(content, invocations[1].range.start), "    ");
+    }
+
+    #[test]
+    fn test_remove_macro_imports() {
+        let content = r#"use hey_chat_gpt::do_it;
+use hey_chat_gpt::{self as gpt, あとは任せた as 任せた};
+use std::collections::{HashMap, HashSet};
+use other::do_it as other_do_it;
+
+fn main() {}
+"#;
+        assert_eq!(
+            remove_macro_imports(content),
+            r#"use hey_chat_gpt::{self as gpt};
+use std::collections::{HashMap, HashSet};
+use other::do_it as other_do_it;
+
+fn main() {}
+"#
+        );
+    }
+
+    #[test]
+    fn test_indent_lines() {
+        assert_eq!(
+            indent_lines("// a\nfn f() {\n\n    1\n}\n", "    "),
+            "// a\n    fn f() {\n\n        1\n    }\n"
+        );
+    }
+}
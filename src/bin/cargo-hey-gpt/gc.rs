@@ -0,0 +1,70 @@
+use anyhow::{bail, Context};
+use std::path::PathBuf;
+
+use hey_chat_gpt_core::config::Config;
+use hey_chat_gpt_core::gc::{collect_garbage, parse_duration, Limits};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut dry_run = false;
+    let mut overrides = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            "--dry-run" => dry_run = true,
+            "--keep-latest-only" => overrides.push((arg.as_str(), None)),
+            "--max-age" | "--max-entries" | "--max-bytes" => {
+                let value = args
+                    .next()
+                    .with_context(|| format!("{} requires a value", arg))?;
+                overrides.push((arg.as_str(), Some(value.as_str())));
+            }
+            _ => bail!("unknown option `{}`", arg),
+        }
+    }
+    crate::set_manifest_dir(manifest_dir)?;
+
+    // コマンドラインの指定は設定ファイルより優先する
+    let mut limits = Limits::from_config(&Config::load()?)?;
+    for (option, value) in overrides {
+        let value = value.unwrap_or_default();
+        match option {
+            "--max-age" => limits.max_age = Some(parse_duration(value)?),
+            "--max-entries" => {
+                limits.max_entries = Some(value.parse().context("invalid --max-entries")?)
+            }
+            "--max-bytes" => limits.max_bytes = Some(value.parse().context("invalid --max-bytes")?),
+            _ => limits.keep_latest_only = true,
+        }
+    }
+    if limits.is_empty() {
+        bail!(
+            "no limits to apply: set `max_age`, `max_entries`, `max_bytes` or `keep_latest_only` \
+             in the configuration file, or pass them as options"
+        );
+    }
+
+    let evicted = collect_garbage(&limits, None, dry_run)?;
+    for entry in &evicted {
+        eprintln!(
+            "    {} {} ({})",
+            if dry_run { "Would remove" } else { "Removed" },
+            entry.cache_file.display(),
+            entry.meta.source.as_deref().unwrap_or("unknown source")
+        );
+    }
+    let bytes = evicted.iter().map(|entry| entry.bytes).sum::<u64>();
+    match (evicted.len(), dry_run) {
+        (0, _) => eprintln!("Nothing to remove"),
+        (n, true) => eprintln!("{} entries ({} bytes) would be removed", n, bytes),
+        (n, false) => eprintln!("Removed {} entries ({} bytes)", n, bytes),
+    }
+
+    Ok(())
+}
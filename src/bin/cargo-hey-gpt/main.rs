@@ -0,0 +1,92 @@
+//! `cargo hey-gpt` command.
+//!
+//! ```bash
+//! cargo install hey_chat_gpt
+//! cargo hey-gpt prefetch
+//! ```
+
+mod audit;
+mod config;
+mod eject;
+mod gc;
+mod prefetch;
+mod report;
+mod unpin;
+
+use anyhow::bail;
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = r#"Usage: cargo hey-gpt <COMMAND> [OPTIONS]
+
+Commands:
+  prefetch [-j <N>]   Send all cache-missing requests of this crate concurrently and fill the cache
+  config [FILE]       Show the effective settings (for the invocation in FILE if given)
+  report              Show the spending recorded in the ledger by file, model and day
+  eject [PATH]        Replace the invocations in PATH (default: the whole crate) with the cached code (--dry-run to only list them)
+  unpin <FILE>...     Remove the outputs pinned for FILE from hey_gpt.lock (--all for every file)
+  gc [LIMITS]         Remove cache entries beyond max_age / max_entries / max_bytes, least recently used first
+                      (--max-age 30d, --max-entries N, --max-bytes N, --keep-latest-only, --dry-run)
+  audit [FILTERS]     Show the audit log of requests (--file, --model, --outcome hit|miss|failed, --since YYYY-MM-DD, --json)
+
+Options:
+  --manifest-dir <DIR>  Crate root (default: current directory)
+  -j, --jobs <N>        Number of parallel requests (default: $HEY_GPT_JOBS or 4)
+  -h, --help            Print help
+"#;
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1).peekable();
+    // `cargo hey-gpt ...` として呼ばれた場合はサブコマンド名が先頭に入る
+    if args.peek().map(String::as_str) == Some("hey-gpt") {
+        args.next();
+    }
+    let args = args.collect::<Vec<_>>();
+
+    let result = match args.first().map(String::as_str) {
+        Some("prefetch") => prefetch::run(&args[1..]),
+        Some("config") => config::run(&args[1..]),
+        Some("report") => report::run(&args[1..]),
+        Some("audit") => audit::run(&args[1..]),
+        Some("eject") => eject::run(&args[1..]),
+        Some("unpin") => unpin::run(&args[1..]),
+        Some("gc") => gc::run(&args[1..]),
+        Some("-h" | "--help") | None => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Some(command) => Err(anyhow::anyhow!(
+            "unknown command `{}`\n\n{}",
+            command,
+            USAGE
+        )),
+    };
+
+    // マクロ展開と違い、コマンドではリモートキャッシュの失敗などを伝えられる
+    for warning in hey_chat_gpt_core::cache::take_warnings() {
+        eprintln!("warning: {}", warning);
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// マクロ展開時と同じく、キャッシュや設定ファイルの場所は CARGO_MANIFEST_DIR から決まる
+fn set_manifest_dir(manifest_dir: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    let manifest_dir = match manifest_dir {
+        Some(dir) => dir,
+        None => env::current_dir()?,
+    };
+    if !manifest_dir.join("Cargo.toml").exists() {
+        bail!("{} is not a crate root", manifest_dir.display());
+    }
+    env::set_var("CARGO_MANIFEST_DIR", &manifest_dir);
+
+    Ok(manifest_dir)
+}
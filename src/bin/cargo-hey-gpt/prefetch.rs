@@ -0,0 +1,151 @@
+use anyhow::{bail, Context};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{env, fs, thread};
+
+use hey_chat_gpt_core::cache::load_cache;
+use hey_chat_gpt_core::config::ContextMode;
+use hey_chat_gpt_core::locate::{find_invocations, find_rust_files};
+use hey_chat_gpt_core::pin::Lock;
+use hey_chat_gpt_core::{
+    build_system_message, config, request_key, standalone_content, strip_private, MacroInput,
+    Request,
+};
+
+const DEFAULT_JOBS: usize = 4;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut jobs = match env::var("HEY_GPT_JOBS") {
+        Ok(jobs) => jobs
+            .parse()
+            .context("HEY_GPT_JOBS must be a positive integer")?,
+        Err(_) => DEFAULT_JOBS,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            "-j" | "--jobs" => {
+                jobs = args
+                    .next()
+                    .context("--jobs requires a value")?
+                    .parse()
+                    .context("--jobs must be a positive integer")?;
+            }
+            _ => bail!("unknown option `{}`", arg),
+        }
+    }
+    if jobs == 0 {
+        bail!("--jobs must be a positive integer");
+    }
+
+    let manifest_dir = crate::set_manifest_dir(manifest_dir)?;
+
+    let api_key = env::var("OPENAI_API_KEY").context("OPENAI_API_KEY is not set")?;
+
+    let requests = collect_requests(&manifest_dir)?;
+    if requests.is_empty() {
+        eprintln!("Nothing to prefetch: every invocation is already cached");
+        return Ok(());
+    }
+    eprintln!(
+        "Prefetching {} request(s) with {} job(s)",
+        requests.len(),
+        jobs
+    );
+
+    let queue = Mutex::new(requests.into_iter());
+    let failures = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..jobs.min(queue.lock().unwrap().len()) {
+            s.spawn(|| loop {
+                let Some(request) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let path = request.source_file_path.clone();
+                match request.send(&api_key) {
+                    Ok(_) => eprintln!("    Fetched {}", path.display()),
+                    Err(err) => {
+                        eprintln!("     Failed {}: {:#}", path.display(), err);
+                        failures.lock().unwrap().push(path);
+                    }
+                }
+            });
+        }
+    });
+
+    let failures = failures.into_inner().unwrap();
+    if !failures.is_empty() {
+        bail!("{} request(s) failed", failures.len());
+    }
+
+    Ok(())
+}
+
+/// クレート内の呼び出しを探し、キャッシュのないものについてリクエストを組み立てる
+fn collect_requests(manifest_dir: &Path) -> anyhow::Result<Vec<Request>> {
+    let mut requests = Vec::new();
+    let mut seen = HashSet::new();
+    let lock = Lock::load()?;
+
+    for source_file_path in find_rust_files(manifest_dir)? {
+        let raw_content = fs::read_to_string(&source_file_path)
+            .with_context(|| format!("failed to read {}", source_file_path.display()))?;
+        let file_content = strip_private(&raw_content);
+
+        for (macro_name, tokens) in find_invocations(&raw_content) {
+            let input = syn::parse2::<MacroInput>(tokens).with_context(|| {
+                format!(
+                    "failed to parse `{}!` in {}",
+                    macro_name,
+                    source_file_path.display()
+                )
+            })?;
+            let settings = config::resolve(&source_file_path, &input.settings)
+                .with_context(|| format!("invalid settings for {}", source_file_path.display()))?;
+            if settings.pin()
+                && lock
+                    .find(&source_file_path, &macro_name, &input.tokens)?
+                    .is_some()
+            {
+                continue;
+            }
+            // 同じファイルの呼び出しでも、マクロや設定が違えば別のキーになる。同じキーは `seen` で1つにまとめる
+            let content = match settings.context() {
+                ContextMode::None => standalone_content(&macro_name, &input)?,
+                _ => file_content.clone(),
+            };
+            let system_message = build_system_message(&macro_name, &settings)?;
+            let key = request_key(
+                &input,
+                &settings,
+                &system_message,
+                &source_file_path,
+                &content,
+            );
+            if load_cache(&key)?.is_some() || !seen.insert(key) {
+                continue;
+            }
+
+            let request = Request::new(
+                &macro_name,
+                &input,
+                settings,
+                &system_message,
+                source_file_path.clone(),
+                content,
+            )
+            .with_context(|| format!("failed to prepare {}", source_file_path.display()))?;
+            requests.push(request);
+        }
+    }
+
+    Ok(requests)
+}
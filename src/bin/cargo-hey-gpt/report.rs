@@ -0,0 +1,134 @@
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use hey_chat_gpt_core::config::Config;
+use hey_chat_gpt_core::ledger::{ledger_path, load_ledger, total_spent, LedgerEntry};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            _ => bail!("unknown option `{}`", arg),
+        }
+    }
+    crate::set_manifest_dir(manifest_dir)?;
+
+    let config = Config::load()?;
+    let entries = load_ledger()?;
+    println!("# Ledger: {}", ledger_path().display());
+    if entries.is_empty() {
+        println!("No requests have been recorded yet");
+        return Ok(());
+    }
+
+    let spent = total_spent(&entries);
+    println!(
+        "Total: {} in {} request(s)",
+        format_cost(Some(spent)),
+        entries.len()
+    );
+    if let Some(budget) = config.budget_usd {
+        println!(
+            "Budget: ${:.2} ({} remaining)",
+            budget,
+            format_cost(Some((budget - spent).max(0.0)))
+        );
+    }
+    if entries.iter().any(|entry| entry.cost_usd.is_none()) {
+        println!("Some models have no price; add them to [pricing] to include them in the total");
+    }
+
+    print_breakdown("File", &entries, |entry| entry.file.clone());
+    print_breakdown("Model", &entries, |entry| entry.model.clone());
+    print_breakdown("Day (UTC)", &entries, |entry| format_day(entry.timestamp));
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Summary {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost_usd: Option<f64>,
+}
+
+fn print_breakdown(title: &str, entries: &[LedgerEntry], key: impl Fn(&LedgerEntry) -> String) {
+    let mut summaries = BTreeMap::<String, Summary>::new();
+    for entry in entries {
+        let summary = summaries.entry(key(entry)).or_default();
+        summary.requests += 1;
+        summary.prompt_tokens += entry.prompt_tokens;
+        summary.completion_tokens += entry.completion_tokens;
+        if let Some(cost) = entry.cost_usd {
+            summary.cost_usd = Some(summary.cost_usd.unwrap_or(0.0) + cost);
+        }
+    }
+
+    let width = summaries
+        .keys()
+        .map(|key| key.chars().count())
+        .chain([title.len()])
+        .max()
+        .unwrap_or(0);
+    println!();
+    println!(
+        "{:<width$}  {:>8}  {:>12}  {:>12}  {:>10}",
+        title, "requests", "prompt", "completion", "cost"
+    );
+    for (key, summary) in summaries {
+        println!(
+            "{:<width$}  {:>8}  {:>12}  {:>12}  {:>10}",
+            key,
+            summary.requests,
+            summary.prompt_tokens,
+            summary.completion_tokens,
+            format_cost(summary.cost_usd)
+        );
+    }
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(cost) => format!("${:.4}", cost),
+        None => "unknown".to_string(),
+    }
+}
+
+/// UNIX時刻を `YYYY-MM-DD` (UTC) にする
+pub fn format_day(timestamp: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_day() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(951_782_400), "2000-02-29");
+        assert_eq!(format_day(1_735_689_599), "2024-12-31");
+        assert_eq!(format_day(1_735_689_600), "2025-01-01");
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{bail, Context};
+use std::path::PathBuf;
+
+use hey_chat_gpt_core::pin::{guard, lock_file_key, Lock, LOCK_FILE_NAME};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut manifest_dir = None;
+    let mut files = Vec::new();
+    let mut all = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-dir" => {
+                manifest_dir = Some(PathBuf::from(
+                    args.next().context("--manifest-dir requires a value")?,
+                ));
+            }
+            "--all" => all = true,
+            _ if arg.starts_with('-') => bail!("unknown option `{}`", arg),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() && !all {
+        bail!("give the files to unpin, or `--all` to unpin everything");
+    }
+    crate::set_manifest_dir(manifest_dir)?;
+
+    let _guard = guard()?;
+    let mut lock = Lock::load()?;
+    let keys = files
+        .iter()
+        .map(|path| lock_file_key(path))
+        .collect::<Vec<_>>();
+    let before = lock.pins.len();
+    lock.pins.retain(|pin| !all && !keys.contains(&pin.file));
+    let removed = before - lock.pins.len();
+
+    if removed == 0 {
+        eprintln!("Nothing pinned");
+        return Ok(());
+    }
+    lock.save()?;
+    eprintln!(
+        "Unpinned {} output(s) in {}; they are generated again on the next build",
+        removed, LOCK_FILE_NAME
+    );
+
+    Ok(())
+}
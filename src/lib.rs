@@ -2,9 +2,9 @@
 //!
 //! ChatGPT APIに実装を代行してもらうマクロです。
 //!
-//! **This crate requires `nightly` toolchain!**
+//! **This crate works on stable Rust 1.88 or later.**
 //!
-//! 本クレートでは `nightly` ツールチェイン必須です！
+//! 本クレートはstableのRust 1.88以降で動きます。
 //!
 //! - [`do_it`](crate::do_it!)
 //! - [`あとは任せた`](crate::あとは任せた!)
@@ -26,8 +26,10 @@
 //! ```
 //!
 //! The difference between the two macros is whether they are in English or Japanese.
+//! Other languages are available with the `lang` option, e.g. `do_it!(lang = "zh")`.
 //!
 //! この2つのマクロの違いは、英語か日本語かです。
+//! その他の言語は `lang` オプションで指定できます (例: `do_it!(lang = "zh")`)。
 //!
 //! Please see the links for each macro for more details.
 //!
@@ -38,32 +40,21 @@
 //! To compile, run the below command.
 //!
 //! ```bash
-//! OPENAI_API_KEY=sk-YOUR-API-KEY RUSTFLAGS='--cfg procmacro2_semver_exempt' cargo +nightly run
+//! OPENAI_API_KEY=sk-YOUR-API-KEY cargo run
 //! ```
 //!
 //! - `OPENAI_API_KEY`: api key.
-//! - `RUSTFLAGS=...`: to enable [`source_file` method](https://doc.rust-lang.org/proc_macro/struct.Span.html#method.source_file) of [Span](https://doc.rust-lang.org/proc_macro/struct.Span.html).
-//! - `cargo +nightly run`: the reason of specify `nightly` is same as above.
 //!
-//! These settings can also be enable by setting files.
+//! Stable Rust 1.88 or later is enough.
+//! On older toolchains, the file of the invocation is found by searching the `.rs` files of the crate, so the same invocation (with the same arguments) must not appear in two files.
 //!
-//! `.cargo/config.toml`
+//! The api key can also be set in `.cargo/config.toml`.
 //!
 //! ```toml:.cargo/config.toml
-//! [build]
-//! rustflags = ["--cfg=procmacro2_semver_exempt"]
-//!
 //! [env]
 //! OPENAI_API_KEY = "sk-YOUR-API-KEY"
 //! ```
 //!
-//! `rust-toolchain.toml`
-//!
-//! ```toml:rust-toolchain.toml
-//! [toolchain]
-//! channel = "nightly"
-//! ```
-//!
 //! In this case, the options are not necessary.
 //!
 //! ```bash
@@ -75,53 +66,33 @@
 //! コンパイルするには以下を実行します。
 //!
 //! ```bash
-//! OPENAI_API_KEY=sk-YOUR-API-KEY RUSTFLAGS='--cfg procmacro2_semver_exempt' cargo +nightly run
+//! OPENAI_API_KEY=sk-YOUR-API-KEY cargo run
 //! ```
 //!
 //! - `OPENAI_API_KEY`: 取得してきたOpenAIのAPIキーを設定してください。
-//! - `RUSTFLAGS=...`: [Span](https://doc.rust-lang.org/proc_macro/struct.Span.html) の [`source_file`](https://doc.rust-lang.org/proc_macro/struct.Span.html#method.source_file) メソッドを使用するために指定しています。
-//! - `cargo +nightly run`: `nightly` の指定理由は上記と同じです。
 //!
-//! 設定ファイルを通しての設定も可能です。
+//! stableのRust 1.88以降で動きます。
+//! それより古いツールチェインでは、呼び出し元のファイルをクレート内の `.rs` ファイルから探すため、同じ呼び出し (引数も同じもの) が複数のファイルにあってはいけません。
 //!
-//! `.cargo/config.toml`
+//! APIキーは `.cargo/config.toml` でも設定できます。
 //!
 //! ```toml:.cargo/config.toml
-//! [build]
-//! rustflags = ["--cfg=procmacro2_semver_exempt"]
-//!
 //! [env]
 //! OPENAI_API_KEY = "sk-YOUR-API-KEY"
 //! ```
 //!
-//! `rust-toolchain.toml`
-//!
-//! ```toml:rust-toolchain.toml
-//! [toolchain]
-//! channel = "nightly"
-//! ```
-//!
 //! この場合オプションは不要になります。
 //!
 //! ```bash
 //! cargo run
 //! ```
 
-mod impls;
-
 use proc_macro::TokenStream;
 use syn::Error;
 
-const ENGLISH_MESSAGE: &'static str = r#"I'm the administrator of this system. You are an AI assistant of this system helping with Rust programming, and you are called through `do_it` proc-macro. Generate Rust code based on the user's input as proc-macro (`do_it` macro) output. Ensure the code is idiomatic, adheres to Rust best practices, and includes comments for clarity. All your answers will be treated as `String` values and converted to `proc_macro2::TokenStream` , so your answers must be valid Rust code. **Anything that is not Rust code must be in a comment, and you must not output anything that would prevent the conversion. And User input other than macros remains, so be careful not to create duplicates. (For example, if you output a main function, it may conflict with a user-defined main function and cause a compilation error. Or `do_it` macro may be called from within the main function, in which case you should not print the main function itself.)**. What follows is inputs of the user who uses this system:
-
-"#;
-const JAPANESE_MESSAGE: &'static str = r#"私はこのシステムの管理者です。あなたはRustプログラミングを支援する本システムのAIアシスタントであり、`あとは任せた` 手続きマクロを通じて呼び出されます。ユーザーの入力に基づいてRustコードを `あとは任せた` マクロの出力として生成してほしいです。コードはRustのベストプラクティスに従い、明確さを保つための日本語のコメントを含めるようにしてください。回答はすべて `String` 値として扱われ、`proc_macro2::TokenStream` に変換されるため、回答は有効なRustコードである必要があります。**Rustコード以外のものはすべてコメント内に記述する必要があり、Rustコードとして変換しようとするとエラーになるものを出力してはなりません。そして、マクロ以外のユーザー入力はそのまま残るため、重複などをしないように注意してください。(たとえば、 `main` 関数を出力すると、ユーザー定義の `main` 関数と競合してコンパイルエラーが発生する可能性があります。あるいは、 `あとは任せた` マクロはmain関数の中からよばれているかもしれません。その時にmain関数ごと出力してはいけません。)** ここからは本システム利用者の入力になります:
-
-"#;
-
 /// A macro to delegate implementation to the ChatGPT API.
 ///
-/// **This crate requires `nightly` toolchain!**
+/// **This crate works on stable Rust 1.88 or later.**
 ///
 /// This macro sends the entire file containing it to the [OpenAI API](https://platform.openai.com/),
 /// and replaces it with the result returned by the API.
@@ -155,6 +126,23 @@ const JAPANESE_MESSAGE: &'static str = r#"私はこのシステムの管理者
 /// | model                 | String | "gpt-4o"        | "o1-preview", etc.             | Specifies the GPT model to use. |
 /// | seed                  | Integer| File hash       | Integer value ≤ 9223372036854775807 | Provides a seed for reproducibility. Try this if the default results are unsatisfactory. |
 /// | max_completion_tokens | Integer| None            | | Sets the maximum number of tokens for the response. Might help when the output is truncated (unverified). |
+/// | code_tags             | String | "rust, rs"      | Comma-separated tags           | Fence language tags (case-insensitive) treated as Rust code in the response. Attributes such as `rust,ignore` are allowed. |
+/// | untagged_fences       | String | "fallback"      | "fallback", "include", "ignore" | How to treat code fences without a language tag. "fallback" uses them only when there is no tagged fence. |
+/// | refine                | String | None            | | An additional instruction for the previous generation of this invocation (told apart from other invocations in the file by the macro and its other arguments). When it changes, the conversation continues from the stored transcript instead of starting over. |
+/// | max_retries           | Integer| 3               | | Number of retries on rate limits (429), server errors (500, 502, 503, 504) and connection errors. Waits with jittered exponential backoff, honouring `Retry-After` and `x-ratelimit-reset-*`. Can also be set with `HEY_GPT_MAX_RETRIES`. |
+/// | timeout_secs          | Integer| 300             | | Timeout of each request in seconds, so that a hung connection never stalls the build. Can also be set with `HEY_GPT_TIMEOUT`. |
+/// | headers               | String | None            | `"Name: value; Name2: value2"` | Extra HTTP headers such as `OpenAI-Organization` or `OpenAI-Project`. Merged over `HEY_GPT_EXTRA_HEADERS`. The file is sent to the API as it is, so put secrets in the environment variable instead. |
+/// | offline               | Boolean| false           | true, false                    | Never send requests and use cached responses only. Can also be set with `HEY_GPT_OFFLINE`. |
+/// | system_prompt         | String | Built-in message | | Your own system message, e.g. house rules such as "no `unsafe`" or "use `thiserror`". `{macro_name}`, `{crate_name}`, `{edition}` and `{features}` (the `default` features in `Cargo.toml`) are replaced. Usually written in the configuration file. |
+/// | system_prompt_file    | String | None            | Path from the crate root       | Reads `system_prompt` from a file. |
+/// | system_prompt_mode    | String | "replace"       | "replace", "extend"            | Whether `system_prompt` replaces the built-in message or is appended to it. |
+/// | lang                  | String | "en" (`あとは任せた`: "ja") | "en", "ja", "zh", "ko", "de", or one added in the configuration file | Language of the system message and of the comments in the generated code. `do_it` and `あとは任せた` are presets of "en" and "ja". Can also be set with `HEY_GPT_LANG`. |
+/// | context               | String | "file"          | "file", "crate", "none"        | "crate" also sends the `[dependencies]` and edition from `Cargo.toml` and the signatures of the public items in the other modules under `src`, so that the model uses only existing crates and reuses existing helpers. This context is part of the cache key, so changing a public signature in another module or a dependency regenerates the code. "none" (or "standalone") does not read the file and sends only the macro input: the prompt and `signatures { ... }` / `examples { ... }` blocks. |
+/// | context_max_tokens    | Integer| 4000            | | Token budget of `context = "crate"`. Modules that do not fit are omitted. |
+/// | max_prompt_tokens     | Integer| Context window  | | Upper limit of the prompt tokens, counted before sending. The context window of the model minus `max_completion_tokens` is always applied. When the prompt is too large, `context = "crate"` is trimmed first, and then the build fails with the token count. No BPE table is bundled: without `tokenizer_file` or `HEY_GPT_TOKENIZER_FILE` the count is an approximate estimate from the text length, so a prompt near the limit may pass or fail wrongly. |
+/// | redaction             | String | "redact"        | "off", "redact", "strict"      | Secrets in the file (AWS, GitHub, OpenAI and Slack keys, private-key PEM blocks, passwords in URLs, high-entropy strings and `redact_patterns`) are replaced with placeholders such as `REDACTED_OPENAI_KEY_1` before sending, and put back into the expanded code. "strict" fails the build instead when a request would be sent; cached responses are still expanded. |
+/// | redact_patterns       | Array  | None            | Regular expressions            | Additional values to redact, such as internal host names. Only in the configuration file. |
+/// | pin                   | Boolean| false           | true, false                    | Record the accepted output in `hey_gpt.lock` and keep using it when the rest of the file changes. See "Pinning". |
 ///
 /// Example with options:
 ///
@@ -182,32 +170,21 @@ const JAPANESE_MESSAGE: &'static str = r#"私はこのシステムの管理者
 /// To compile, run the below command.
 ///
 /// ```bash
-/// OPENAI_API_KEY=sk-YOUR-API-KEY RUSTFLAGS='--cfg procmacro2_semver_exempt' cargo +nightly run
+/// OPENAI_API_KEY=sk-YOUR-API-KEY cargo run
 /// ```
 ///
 /// - `OPENAI_API_KEY`: api key.
-/// - `RUSTFLAGS=...`: to enable [`source_file` method](https://doc.rust-lang.org/proc_macro/struct.Span.html#method.source_file) of [Span](https://doc.rust-lang.org/proc_macro/struct.Span.html).
-/// - `cargo +nightly run`: the reason of specify `nightly` is same as above.
 ///
-/// These settings can also be enable by setting files.
+/// Stable Rust 1.88 or later is enough.
+/// On older toolchains, the file of the invocation is found by searching the `.rs` files of the crate, so the same invocation (with the same arguments) must not appear in two files.
 ///
-/// `.cargo/config.toml`
+/// The api key can also be set in `.cargo/config.toml`.
 ///
 /// ```toml:.cargo/config.toml
-/// [build]
-/// rustflags = ["--cfg=procmacro2_semver_exempt"]
-///
 /// [env]
 /// OPENAI_API_KEY = "sk-YOUR-API-KEY"
 /// ```
 ///
-/// `rust-toolchain.toml`
-///
-/// ```toml:rust-toolchain.toml
-/// [toolchain]
-/// channel = "nightly"
-/// ```
-///
 /// In this case, the options are not necessary.
 ///
 /// ```bash
@@ -215,16 +192,16 @@ const JAPANESE_MESSAGE: &'static str = r#"私はこのシステムの管理者
 /// ```
 #[proc_macro]
 pub fn do_it(input: TokenStream) -> TokenStream {
-    let input = syn::parse_macro_input!(input as impls::MacroInput);
+    let input = syn::parse_macro_input!(input as hey_chat_gpt_core::MacroInput);
 
-    impls::do_it(input, ENGLISH_MESSAGE)
+    hey_chat_gpt_core::do_it(input, "do_it")
         .unwrap_or_else(Error::into_compile_error)
         .into()
 }
 
 /// ChatGPT APIに実装を代行してもらうマクロです。
 ///
-/// **本クレートでは `nightly` ツールチェイン必須です！**
+/// **本クレートはstableのRust 1.88以降で動きます。**
 ///
 /// このマクロを記述したファイル全体を[OpenAI API](https://platform.openai.com/)に投げ、返ってきた結果で置換します。
 ///
@@ -255,6 +232,23 @@ pub fn do_it(input: TokenStream) -> TokenStream {
 /// | model                 | 文字列  | "gpt-4o"       | "o1-preview" 等                | 使用するGPTのモデルを指定します。 |
 /// | seed                  | 整数値 | ファイルハッシュ   | 9223372036854775807 以下の整数値 | 再現性確保のために与えるシード値を与えます。デフォルトだと芳しくない結果になった時に指定してみてください。 |
 /// | max_completion_tokens | 整数値 | 指定なし          | | 返答の最大トークン数を設定します。生成が中途半端になった時に使えるかも...？(未検証) |
+/// | code_tags             | 文字列  | "rust, rs"     | カンマ区切りのタグ               | 返答中でRustコードとして扱うコードブロックの言語タグです(大文字小文字は区別しません)。 `rust,ignore` のような属性付きのものも対象になります。 |
+/// | untagged_fences       | 文字列  | "fallback"     | "fallback", "include", "ignore" | 言語タグのないコードブロックの扱いです。 "fallback" ではタグ付きのブロックがない時のみ使用します。 |
+/// | refine                | 文字列  | 指定なし          | | この呼び出しの前回の生成に対する追加の指示です (同じファイルの他の呼び出しとはマクロと他の引数で区別します)。値を変えると、最初からではなく保存された会話履歴の続きとして生成し直します。 |
+/// | max_retries           | 整数値 | 3              | | レート制限(429)、サーバーエラー(500, 502, 503, 504)、接続エラー時の再試行回数です。ジッター付きの指数バックオフで待ち、 `Retry-After` や `x-ratelimit-reset-*` があればそれに従います。 `HEY_GPT_MAX_RETRIES` でも指定できます。 |
+/// | timeout_secs          | 整数値 | 300            | | 各リクエストのタイムアウト秒数です。接続が固まってもビルドが止まり続けることはありません。 `HEY_GPT_TIMEOUT` でも指定できます。 |
+/// | headers               | 文字列  | 指定なし          | `"Name: value; Name2: value2"` | `OpenAI-Organization` や `OpenAI-Project` などの追加のHTTPヘッダです。 `HEY_GPT_EXTRA_HEADERS` に追加され、同じ名前のヘッダはこちらが優先されます。ファイルはそのままAPIに送られるため、秘密の値は環境変数の方に設定してください。 |
+/// | offline               | 真偽値 | false          | true, false                    | リクエストを送らず、キャッシュのみを使います。 `HEY_GPT_OFFLINE` でも指定できます。 |
+/// | system_prompt         | 文字列  | 組み込みのメッセージ | | 独自のシステムメッセージです。「`unsafe` 禁止」「`thiserror` を使う」などのチームのルールを書けます。 `{macro_name}`, `{crate_name}`, `{edition}`, `{features}` (`Cargo.toml` の `default` フィーチャー) は置換されます。通常は設定ファイルに書きます。 |
+/// | system_prompt_file    | 文字列  | 指定なし          | クレートのルートからのパス       | `system_prompt` をファイルから読みます。 |
+/// | system_prompt_mode    | 文字列  | "replace"      | "replace", "extend"            | `system_prompt` で組み込みのメッセージを置き換えるか、追記するかです。 |
+/// | lang                  | 文字列  | "ja" (`do_it`: "en") | "en", "ja", "zh", "ko", "de" または設定ファイルで追加したもの | システムメッセージと生成されるコードのコメントの言語です。 `do_it` と `あとは任せた` はそれぞれ "en" と "ja" のプリセットです。 `HEY_GPT_LANG` でも指定できます。 |
+/// | context               | 文字列  | "file"         | "file", "crate", "none"        | "crate" では `Cargo.toml` の `[dependencies]` とエディション、 `src` 以下の他のモジュールの公開アイテムのシグネチャも送ります。存在しないクレートを使ったり、既存のヘルパーを作り直したりしにくくなります。この情報もキャッシュのキーに含まれるため、他のモジュールの公開シグネチャや依存関係を変えると生成し直します。 "none" ( "standalone" も可) ではファイルを読まず、マクロの入力 (プロンプトと `signatures { ... }` / `examples { ... }` ブロック) だけを送ります。 |
+/// | context_max_tokens    | 整数値 | 4000           | | `context = "crate"` で送る情報のトークン数の上限です。収まらないモジュールは省かれます。 |
+/// | max_prompt_tokens     | 整数値 | コンテキストウィンドウ | | 送信前に数えるプロンプトのトークン数の上限です。モデルのコンテキストウィンドウから `max_completion_tokens` を引いたものは常に適用されます。大きすぎる場合はまず `context = "crate"` の情報を削り、それでも収まらなければトークン数を示してエラーにします。BPEテーブルは同梱していないため、 `tokenizer_file` か `HEY_GPT_TOKENIZER_FILE` の指定がない場合は文字数からの概算となり、上限に近いプロンプトは誤って通ったりエラーになったりすることがあります。 |
+/// | redaction             | 文字列  | "redact"       | "off", "redact", "strict"      | ファイル中の秘密の値 (AWS・GitHub・OpenAI・Slackのキー、秘密鍵のPEM、URL中のパスワード、エントロピーの高い文字列、 `redact_patterns`) を送信前に `REDACTED_OPENAI_KEY_1` のようなプレースホルダに置き換え、展開されるコードでは元に戻します。 "strict" ではリクエストを送る時にビルドを失敗させます (キャッシュされた応答はそのまま展開します)。 |
+/// | redact_patterns       | 配列   | 指定なし          | 正規表現                        | 内部のホスト名など、追加で伏せる値です。設定ファイルでのみ指定できます。 |
+/// | pin                   | 真偽値 | false          | true, false                    | 採用した出力を `hey_gpt.lock` に記録し、ファイルの他の場所が変わってもそれを使い続けます。「ピン留め」を参照。 |
 ///
 /// オプションを指定した場合の例
 ///
@@ -282,32 +276,21 @@ pub fn do_it(input: TokenStream) -> TokenStream {
 /// コンパイルするには以下を実行します。
 ///
 /// ```bash
-/// OPENAI_API_KEY=sk-YOUR-API-KEY RUSTFLAGS='--cfg procmacro2_semver_exempt' cargo +nightly run
+/// OPENAI_API_KEY=sk-YOUR-API-KEY cargo run
 /// ```
 ///
 /// - `OPENAI_API_KEY`: 取得してきたOpenAIのAPIキーを設定してください。
-/// - `RUSTFLAGS=...`: [Span](https://doc.rust-lang.org/proc_macro/struct.Span.html) の [`source_file`](https://doc.rust-lang.org/proc_macro/struct.Span.html#method.source_file) メソッドを使用するために指定しています。
-/// - `cargo +nightly run`: `nightly` の指定理由は上記と同じです。
 ///
-/// 設定ファイルを通しての設定も可能です。
+/// stableのRust 1.88以降で動きます。
+/// それより古いツールチェインでは、呼び出し元のファイルをクレート内の `.rs` ファイルから探すため、同じ呼び出し (引数も同じもの) が複数のファイルにあってはいけません。
 ///
-/// `.cargo/config.toml`
+/// APIキーは `.cargo/config.toml` でも設定できます。
 ///
 /// ```toml:.cargo/config.toml
-/// [build]
-/// rustflags = ["--cfg=procmacro2_semver_exempt"]
-///
 /// [env]
 /// OPENAI_API_KEY = "sk-YOUR-API-KEY"
 /// ```
 ///
-/// `rust-toolchain.toml`
-///
-/// ```toml:rust-toolchain.toml
-/// [toolchain]
-/// channel = "nightly"
-/// ```
-///
 /// この場合オプションは不要になります。
 ///
 /// ```bash
@@ -315,9 +298,25 @@ pub fn do_it(input: TokenStream) -> TokenStream {
 /// ```
 #[proc_macro]
 pub fn あとは任せた(input: TokenStream) -> TokenStream {
-    let input = syn::parse_macro_input!(input as impls::MacroInput);
+    let input = syn::parse_macro_input!(input as hey_chat_gpt_core::MacroInput);
 
-    impls::do_it(input, JAPANESE_MESSAGE)
+    hey_chat_gpt_core::do_it(input, "あとは任せた")
         .unwrap_or_else(Error::into_compile_error)
         .into()
 }
+
+/// Marks an item whose body must not be sent to ChatGPT.
+///
+/// The item itself is left unchanged; only its signature is included in the prompt.
+/// Use it as `#[hey_chat_gpt::private]`, or as `#[hey_gpt::private]` after `use hey_chat_gpt as hey_gpt;`.
+///
+/// ```ignore
+/// #[hey_chat_gpt::private]
+/// fn licensed_algorithm() -> u32 {
+///     42
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn private(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}